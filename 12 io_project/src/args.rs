@@ -0,0 +1,233 @@
+// splits the command line into flags and values, the way getopt does:
+// `-inr` is three flags, `-A2` and `-A 2` both give `-A` the value 2, so do
+// `--after-context=2` and `--after-context 2`, and everything after `--` is a
+// value even if it starts with `-`
+
+use std::error::Error;
+use std::fmt;
+
+pub const HELP: &str = "\
+Usage: minigrep [OPTIONS] QUERY [PATH]...
+
+Prints the lines of each PATH that contain QUERY. Directories are searched
+recursively, skipping hidden files and those ignored by .gitignore or .ignore.
+With no PATH, or a PATH of -, standard input is searched.
+
+Options:
+  -i, --ignore-case         Match either case, even without IGNORE_CASE set
+      --no-ignore-case      Match case exactly, even with IGNORE_CASE set
+  -E, --extended-regexp     Treat QUERY as a regular expression
+  -v, --invert-match        Select the lines that don't match instead
+  -m, --max-count NUM       Stop reading a file after NUM selected lines
+  -r, --recursive           Search the current directory if no PATH is given
+      --glob GLOB           Only search files matching GLOB, e.g. '*.rs'
+      --exclude GLOB        Skip files and directories matching GLOB
+      --hidden              Search hidden files and directories too
+      --mmap                Map files into memory rather than reading them
+  -j, --threads NUM         Search NUM files at once; 0, the default, for one
+                            per CPU
+      --sort path|none      Print files in path order, the same every run, or
+                            as they're done, the default
+  -c, --count               Print only how many lines each file has selected
+  -l, --files-with-matches  Print only the names of files with selected lines
+  -L, --files-without-match Print only the names of files without any
+  -q, --quiet, --silent     Print nothing, only exit with the status
+      --json                Print matches and context as JSON, one event to a
+                            line; see src/json.rs for the schema
+  -n, --line-number         Print each line's number
+  -b, --byte-offset         Print the byte offset of each line's start
+  -H, --with-filename       Print the file name for each line
+  -h, --no-filename         Never print file names
+  -A, --after-context NUM   Print NUM lines after each match
+  -B, --before-context NUM  Print NUM lines before each match
+  -C, --context NUM         Print NUM lines before and after each match
+      --color[=WHEN]        Highlight matches, file names and line numbers
+                            never, always, or auto, the default, for when
+                            printing to a terminal; GREP_COLORS picks colors
+      --help                Print this help
+  -V, --version             Print the version
+
+Exits with 0 if a line was selected, 1 if none was, and 2 on an error.
+";
+
+pub const VERSION: &str = concat!("minigrep ", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, PartialEq)]
+pub enum ArgsError {
+    // not failures, but the parse stops there all the same
+    Help,
+    Version,
+    UnknownFlag(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue { flag: String, value: String },
+    MissingQuery,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::Version => write!(f, "version requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "unknown flag {flag}, see --help"),
+            ArgsError::MissingValue(flag) => write!(f, "{flag} needs a value"),
+            ArgsError::UnexpectedValue(flag) => write!(f, "{flag} doesn't take a value"),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value {value:?} for {flag}")
+            }
+            ArgsError::MissingQuery => write!(f, "Didn't get a query string"),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+    // with its dashes, e.g. `-i` or `--ignore-case`
+    Flag(String),
+    Value(String),
+}
+
+pub struct Args<I> {
+    args: I,
+    // the rest of a group of short flags like `-inr`
+    shorts: Vec<char>,
+    // the part after `=` of the last long flag
+    long_value: Option<String>,
+    // the last flag, for errors about its value
+    flag: String,
+    // after `--`
+    values_only: bool,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    pub fn new(args: I) -> Args<I> {
+        Args {
+            args,
+            shorts: Vec::new(),
+            long_value: None,
+            flag: String::new(),
+            values_only: false,
+        }
+    }
+
+    pub fn next_arg(&mut self) -> Result<Option<Arg>, ArgsError> {
+        if self.long_value.is_some() {
+            return Err(ArgsError::UnexpectedValue(self.flag.clone()));
+        }
+        if !self.shorts.is_empty() {
+            let c = self.shorts.remove(0);
+            self.flag = format!("-{c}");
+            return Ok(Some(Arg::Flag(self.flag.clone())));
+        }
+
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+        if self.values_only {
+            return Ok(Some(Arg::Value(arg)));
+        }
+
+        if arg == "--" {
+            self.values_only = true;
+            return self.next_arg();
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            self.flag = format!("--{name}");
+            self.long_value = value;
+            return Ok(Some(Arg::Flag(self.flag.clone())));
+        }
+        // a lone `-` is a value, conventionally standard input
+        if arg.len() > 1 && arg.starts_with('-') {
+            self.shorts = arg.chars().skip(1).collect();
+            return self.next_arg();
+        }
+        Ok(Some(Arg::Value(arg)))
+    }
+
+    /// The value of the flag just returned: what follows it in the same argument,
+    /// or else the next argument.
+    pub fn value(&mut self) -> Result<String, ArgsError> {
+        if let Some(value) = self.long_value.take() {
+            return Ok(value);
+        }
+        if !self.shorts.is_empty() {
+            return Ok(self.shorts.drain(..).collect());
+        }
+        self.args
+            .next()
+            .ok_or_else(|| ArgsError::MissingValue(self.flag.clone()))
+    }
+
+    /// The value after `=` of the long flag just returned, for a flag whose
+    /// value is optional, so it never takes the next argument.
+    pub fn attached_value(&mut self) -> Option<String> {
+        self.long_value.take()
+    }
+
+    /// Like [`Args::value`], parsed as a number.
+    pub fn number(&mut self) -> Result<usize, ArgsError> {
+        let value = self.value()?;
+        value.parse().map_err(|_| ArgsError::InvalidValue {
+            flag: self.flag.clone(),
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(args: &[&str]) -> Vec<Arg> {
+        let mut args = Args::new(args.iter().map(|arg| arg.to_string()));
+        let mut split = Vec::new();
+        while let Some(arg) = args.next_arg().unwrap() {
+            let is_context = arg == Arg::Flag("-A".to_string())
+                || arg == Arg::Flag("--after-context".to_string());
+            split.push(arg);
+            if is_context {
+                split.push(Arg::Value(args.value().unwrap()));
+            }
+        }
+        split
+    }
+
+    #[test]
+    fn splits_combined_flags_and_values() {
+        let flag = |flag: &str| Arg::Flag(flag.to_string());
+        let value = |value: &str| Arg::Value(value.to_string());
+
+        assert_eq!(
+            vec![flag("-i"), flag("-n"), flag("-A"), value("2"), value("x")],
+            split(&["-inA2", "x"])
+        );
+        assert_eq!(
+            vec![flag("--after-context"), value("3"), flag("-A"), value("4")],
+            split(&["--after-context=3", "-A", "4"])
+        );
+        assert_eq!(
+            vec![flag("-n"), value("-"), value("-i"), value("--x")],
+            split(&["-n", "-", "--", "-i", "--x"])
+        );
+    }
+
+    #[test]
+    fn rejects_stray_and_missing_values() {
+        let mut args = Args::new(["--hidden=yes".to_string()].into_iter());
+        args.next_arg().unwrap();
+        assert_eq!(
+            Err(ArgsError::UnexpectedValue("--hidden".to_string())),
+            args.next_arg()
+        );
+
+        let mut args = Args::new(["-A".to_string()].into_iter());
+        args.next_arg().unwrap();
+        assert_eq!(Err(ArgsError::MissingValue("-A".to_string())), args.value());
+    }
+}
@@ -0,0 +1,131 @@
+// ANSI colors for the output, set the way grep's are, through `GREP_COLORS`:
+// colon-separated `name=SGR` pairs like `ms=01;31:fn=35`, where the SGR
+// parameters are those of the terminal's "select graphic rendition" escape
+
+use std::io::{self, IsTerminal};
+
+/// When to color the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ColorChoice {
+    // when standard output is a terminal that can show colors
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" | "tty" | "if-tty" => Some(ColorChoice::Auto),
+            "always" | "yes" | "force" => Some(ColorChoice::Always),
+            "never" | "no" | "none" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    /// The colors to use, if any, reading `GREP_COLORS` for them.
+    pub fn colors(self) -> Option<Colors> {
+        let colored = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                io::stdout().is_terminal()
+                    && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+        };
+        colored.then(|| Colors::parse(&std::env::var("GREP_COLORS").unwrap_or_default()))
+    }
+}
+
+/// The SGR parameters for each part of the output; empty leaves a part as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    // matched text in a selected line, and in a context line
+    pub selected_match: String,
+    pub context_match: String,
+    // the rest of a selected line, and of a context line
+    pub selected_line: String,
+    pub context_line: String,
+    pub filename: String,
+    pub line_number: String,
+    pub byte_offset: String,
+    // the `:` and `-` after each prefix, and `--` between groups
+    pub separator: String,
+}
+
+impl Default for Colors {
+    // grep's defaults
+    fn default() -> Colors {
+        Colors {
+            selected_match: "01;31".to_string(),
+            context_match: "01;31".to_string(),
+            selected_line: String::new(),
+            context_line: String::new(),
+            filename: "35".to_string(),
+            line_number: "32".to_string(),
+            byte_offset: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+impl Colors {
+    /// The defaults, with whatever `spec` sets in `GREP_COLORS` syntax. Names
+    /// it doesn't know, and values that aren't SGR parameters, are ignored, as
+    /// grep ignores them.
+    pub fn parse(spec: &str) -> Colors {
+        let mut colors = Colors::default();
+        for (name, value) in spec.split(':').filter_map(|pair| pair.split_once('=')) {
+            if !value.bytes().all(|b| b.is_ascii_digit() || b == b';') {
+                continue;
+            }
+            let value = value.to_string();
+            match name {
+                "mt" => {
+                    colors.selected_match = value.clone();
+                    colors.context_match = value;
+                }
+                "ms" => colors.selected_match = value,
+                "mc" => colors.context_match = value,
+                "sl" => colors.selected_line = value,
+                "cx" => colors.context_line = value,
+                "fn" => colors.filename = value,
+                "ln" => colors.line_number = value,
+                "bn" => colors.byte_offset = value,
+                "se" => colors.separator = value,
+                _ => {}
+            }
+        }
+        colors
+    }
+}
+
+/// `text` in the color `sgr`, or as it is if `sgr` is empty.
+pub fn paint(sgr: &str, text: &str) -> String {
+    if sgr.is_empty() || text.is_empty() {
+        text.to_string()
+    } else {
+        // erasing to the end of the line keeps a background color from
+        // spilling over when the terminal scrolls
+        format!("\x1b[{sgr}m\x1b[K{text}\x1b[m\x1b[K")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_grep_colors() {
+        let colors = Colors::parse("mt=04:fn=:ln=1;33:bogus=7:se=red");
+        assert_eq!("04", colors.selected_match);
+        assert_eq!("04", colors.context_match);
+        assert_eq!("", colors.filename);
+        assert_eq!("1;33", colors.line_number);
+        assert_eq!("36", colors.separator);
+
+        assert_eq!("\x1b[32m\x1b[K12\x1b[m\x1b[K", paint("32", "12"));
+        assert_eq!("12", paint("", "12"));
+    }
+}
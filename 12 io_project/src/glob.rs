@@ -0,0 +1,137 @@
+// shell-style patterns, for `--glob`, `--exclude` and ignore files
+// `*` and `?` stay within one path component, `**` crosses any number of them,
+// and `[...]` matches one character from a set, `[!...]` or `[^...]` one not in it
+
+use std::collections::HashSet;
+
+/// A pattern matched against `/`-separated paths.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob {
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        matches(&self.pattern, &path, &mut HashSet::new())
+    }
+
+    /// Whether the pattern has a `/` other than a trailing one, which makes it
+    /// match whole paths rather than file names.
+    pub fn has_separator(&self) -> bool {
+        let trimmed = self.pattern.strip_suffix(&['/']).unwrap_or(&self.pattern);
+        trimmed.contains(&'/')
+    }
+}
+
+// `failed` holds the pattern and text left, by their lengths, that are already
+// known not to match, so backtracking through many `*`s tries each pair once
+// rather than once for every way of reaching it
+fn matches(pattern: &[char], text: &[char], failed: &mut HashSet<(usize, usize)>) -> bool {
+    let left = (pattern.len(), text.len());
+    if failed.contains(&left) {
+        return false;
+    }
+
+    let matched = match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // zero directories, or skip to after each `/`
+            matches(rest, text, failed)
+                || text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &c)| c == '/' && matches(rest, &text[i + 1..], failed))
+        }
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..], failed)),
+        ['*', rest @ ..] => {
+            let component = text.iter().position(|&c| c == '/').unwrap_or(text.len());
+            (0..=component).any(|i| matches(rest, &text[i..], failed))
+        }
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => matches(rest, text, failed),
+            _ => false,
+        },
+        ['[', rest @ ..] => match (class(rest), text) {
+            (Some((found, rest)), [c, text @ ..]) => {
+                *c != '/' && found(*c) && matches(rest, text, failed)
+            }
+            // no closing `]`, so a literal `[`
+            (None, ['[', text @ ..]) => matches(rest, text, failed),
+            _ => false,
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => matches(rest, text, failed),
+            _ => false,
+        },
+    };
+    if !matched {
+        failed.insert(left);
+    }
+    matched
+}
+
+// parses the set after a `[`, returning whether a char is in it and the pattern
+// after the closing `]`
+fn class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, &[char])> {
+    let (negated, body) = match pattern {
+        ['!' | '^', body @ ..] => (true, body),
+        body => (false, body),
+    };
+    // a `]` right at the start is a literal
+    let close = 1 + body.get(1..)?.iter().position(|&c| c == ']')?;
+    let set = &body[..close];
+
+    let found = move |c: char| {
+        let mut i = 0;
+        let mut hit = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                hit |= set[i] <= c && c <= set[i + 2];
+                i += 3;
+            } else {
+                hit |= set[i] == c;
+                i += 1;
+            }
+        }
+        hit != negated
+    };
+    Some((found, &body[close + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_stay_in_their_component() {
+        assert!(Glob::new("*.rs").matches("main.rs"));
+        assert!(!Glob::new("*.rs").matches("src/main.rs"));
+        assert!(Glob::new("src/*.rs").matches("src/main.rs"));
+        assert!(Glob::new("file?.[a-c]").matches("file1.b"));
+        assert!(!Glob::new("file[!0-9]").matches("file1"));
+    }
+
+    #[test]
+    fn double_stars_cross_directories() {
+        assert!(Glob::new("target/**").matches("target/debug/build"));
+        assert!(Glob::new("target/**").matches("target/"));
+        assert!(Glob::new("**/test.rs").matches("test.rs"));
+        assert!(Glob::new("**/test.rs").matches("a/b/test.rs"));
+        assert!(Glob::new("a/**/b").matches("a/x/y/b"));
+        assert!(!Glob::new("a/**/b").matches("ab"));
+    }
+
+    #[test]
+    fn many_stars_match_quickly() {
+        let text = "a".repeat(40);
+        assert!(!Glob::new("*a*a*a*a*a*a*a*a*a*a*b").matches(&text));
+        assert!(!Glob::new("**a**a**a**a**a**a**a**a**b").matches(&text));
+    }
+}
@@ -0,0 +1,167 @@
+// the events `--json` prints, one JSON object to a line. The schema is stable:
+// fields may be added, but none will be renamed, removed or change type.
+//
+//   {"type":"begin","path":DATA}
+//     before the first line printed from a file; files with nothing to print
+//     get no events at all
+//   {"type":"match","path":DATA,"line_number":N,"byte_offset":N,"line":DATA,
+//    "submatches":[{"match":DATA,"start":N,"end":N},...]}
+//     a selected line; `line_number` counts from 1, `byte_offset` is where the
+//     line starts in the file, and `line` leaves out its `\n` or `\r\n`
+//   {"type":"context", and the rest as for "match"}
+//     a line printed for `-A`, `-B` or `-C`
+//   {"type":"end","path":DATA,"stats":{"matched_lines":N,"matches":N}}
+//     after the last line printed from a file
+//   {"type":"summary","stats":{"files_searched":N,"files_matched":N,
+//    "matched_lines":N,"matches":N}}
+//     once, last of all
+//
+// DATA is {"text":"..."} for UTF-8, or else {"bytes":"..."} with the raw bytes
+// in standard base64, so paths and lines that aren't UTF-8 come through exactly.
+// `start` and `end` are byte offsets into `line`, and a submatch spans at least
+// one byte; `-v` selects lines without any, and lines that aren't UTF-8 are
+// never given any either
+
+use crate::output::Stats;
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+
+/// The event for the start of the output from `path`.
+pub fn begin(path: &Path) -> String {
+    format!(r#"{{"type":"begin","path":{}}}"#, data(path_bytes(path)))
+}
+
+/// The event for a printed line, a match if `selected` or else context, with
+/// `spans` the byte ranges of the matches in it.
+pub fn line(
+    path: &Path,
+    line_number: usize,
+    byte_offset: u64,
+    line: &[u8],
+    selected: bool,
+    spans: &[Range<usize>],
+) -> String {
+    let kind = if selected { "match" } else { "context" };
+    let mut event = format!(
+        r#"{{"type":"{kind}","path":{},"line_number":{line_number},"byte_offset":{byte_offset},"line":{},"submatches":["#,
+        data(path_bytes(path)),
+        data(line),
+    );
+
+    for (i, span) in spans.iter().enumerate() {
+        if i > 0 {
+            event.push(',');
+        }
+        let _ = write!(
+            event,
+            r#"{{"match":{},"start":{},"end":{}}}"#,
+            data(&line[span.clone()]),
+            span.start,
+            span.end
+        );
+    }
+    event.push_str("]}");
+    event
+}
+
+/// The event for the end of the output from `path`.
+pub fn end(path: &Path, stats: &Stats) -> String {
+    format!(
+        r#"{{"type":"end","path":{},"stats":{{"matched_lines":{},"matches":{}}}}}"#,
+        data(path_bytes(path)),
+        stats.matched_lines,
+        stats.matches
+    )
+}
+
+/// The event for the end of the whole search.
+pub fn summary(stats: &Stats) -> String {
+    format!(
+        r#"{{"type":"summary","stats":{{"files_searched":{},"files_matched":{},"matched_lines":{},"matches":{}}}}}"#,
+        stats.files_searched, stats.files_matched, stats.matched_lines, stats.matches
+    )
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> &[u8] {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes()
+}
+
+// elsewhere paths needn't be bytes at all, so the best there is is their text
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> &[u8] {
+    path.to_str().unwrap_or("\u{FFFD}").as_bytes()
+}
+
+// `{"text":...}` if the bytes are UTF-8, else `{"bytes":...}`
+fn data(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => format!(r#"{{"text":{}}}"#, string(text)),
+        Err(_) => format!(r#"{{"bytes":"{}"}}"#, base64(bytes)),
+    }
+}
+
+// `text` as a JSON string, quoted and escaped
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        // a chunk of k bytes fills k + 1 characters, and `=` pads out the rest
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text_and_encodes_bytes() {
+        assert_eq!(r#""a\"b\\c\n\t\u0001é""#, string("a\"b\\c\n\t\u{1}é"));
+        assert_eq!(r#"{"text":"ok"}"#, data(b"ok"));
+        assert_eq!(r#"{"bytes":"/w=="}"#, data(b"\xff"));
+        assert_eq!("", base64(b""));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+        assert_eq!("Zm9vYg==", base64(b"foob"));
+        assert_eq!("Zm9vYmE=", base64(b"fooba"));
+
+        assert_eq!(
+            r#"{"type":"match","path":{"text":"a.txt"},"line_number":2,"byte_offset":4,"line":{"bytes":"eP94"},"submatches":[]}"#,
+            line(Path::new("a.txt"), 2, 4, b"x\xffx", true, &[])
+        );
+    }
+}
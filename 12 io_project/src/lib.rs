@@ -1,87 +1,341 @@
 use std::env;
 use std::error::Error;
-use std::fs;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+pub mod args;
+pub mod color;
+pub mod glob;
+pub mod json;
+pub mod lines;
+pub mod matcher;
+pub mod output;
+pub mod parallel;
+pub mod regex;
+pub mod walk;
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+use args::{Arg, Args, ArgsError};
+use color::ColorChoice;
+use glob::Glob;
+use lines::LineReader;
+use matcher::Matcher;
+use output::{Format, Mode, Printer};
+use parallel::Summary;
+use walk::{Filter, Walk, WalkError};
+
+/// Searches as configured, returning whether anything matched; with `-L`,
+/// whether any file didn't.
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    // compiled before reading anything, so a bad pattern fails fast
+    let matcher = Matcher::new(
+        &config.query,
+        config.regex,
+        config.ignore_case,
+        config.invert,
+    )?;
+    let quiet = config.format.mode == Mode::Quiet;
+    let json = config.format.mode == Mode::Json;
+
+    // one file gains nothing from more threads, and on one thread it's printed
+    // as it's read rather than held in memory until it's done
+    let one_file = config.paths.len() == 1 && !config.paths[0].is_dir();
+    let walk = Walk::new(&config.paths, config.filter);
+    let mut out = io::stdout().lock();
+    let summary = if config.threads == 1 || one_file {
+        search_in_turn(walk, quiet, config.format, &mut out, |printer, path| {
+            search_path(printer, path, config.mmap, &matcher)
+        })
     } else {
-        search(&config.query, &contents)
+        parallel::search(
+            walk,
+            config.threads,
+            config.sort_by_path,
+            quiet,
+            &config.format,
+            &mut out,
+            |printer, path| search_path(printer, path, config.mmap, &matcher),
+        )
     };
 
-    for line in results {
-        println!("{line}");
+    let summary = summary?;
+    // whatever reads the output has stopped reading, so there's no point
+    if json && !summary.closed {
+        match writeln!(out, "{}", json::summary(&summary.stats)) {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            written => written?,
+        }
     }
+    // like grep, a match is all `-q` answers for, whatever couldn't be searched
+    if summary.failed > 0 && !(quiet && summary.matched) {
+        return Err(format!("couldn't search {} path(s)", summary.failed).into());
+    }
+    Ok(summary.matched)
+}
+
+// searches the files of `walk` one after the other, printing straight to `out`
+fn search_in_turn<W: Write>(
+    walk: Walk,
+    stop_at_match: bool,
+    format: Format,
+    out: W,
+    search: impl Fn(&mut Printer<W>, &Path) -> io::Result<()>,
+) -> io::Result<Summary> {
+    let mut printer = Printer::new(format, out);
+    let mut failed = 0;
+    let mut closed = false;
+
+    for path in walk {
+        let searched = path.and_then(|path| {
+            search(&mut printer, &path).map_err(|error| WalkError { path, error })
+        });
 
-    Ok(())
+        match searched {
+            Ok(()) => {}
+            Err(e) if e.error.kind() == io::ErrorKind::BrokenPipe => {
+                closed = true;
+                break;
+            }
+            // one unreadable file shouldn't stop the search of the rest
+            Err(e) => {
+                eprintln!("{e}");
+                failed += 1;
+            }
+        }
+
+        // the answer is known, and nothing else would be printed
+        if stop_at_match && printer.matched() {
+            break;
+        }
+    }
+
+    Ok(Summary {
+        matched: printer.matched(),
+        failed,
+        stats: printer.stats(),
+        closed,
+    })
+}
+
+// searches one file, or standard input for `-`, skipping it if it looks binary
+fn search_path(
+    printer: &mut Printer<impl Write>,
+    path: &Path,
+    mmap: bool,
+    matcher: &Matcher,
+) -> io::Result<()> {
+    fn search(
+        printer: &mut Printer<impl Write>,
+        path: &Path,
+        mut lines: LineReader<impl io::BufRead>,
+        matcher: &Matcher,
+    ) -> io::Result<()> {
+        if lines.looks_binary()? {
+            return Ok(());
+        }
+        printer.print_file(path, &mut lines, matcher)
+    }
+
+    if path == Path::new("-") {
+        let stdin = LineReader::new(io::stdin().lock());
+        return search(printer, Path::new("(standard input)"), stdin, matcher);
+    }
+
+    let file = File::open(path)?;
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    if mmap {
+        let map = lines::Mmap::open(&file)?;
+        if lines::looks_binary(&map) {
+            return Ok(());
+        }
+        return printer.print_file(path, &mut lines::SliceLines::new(&map), matcher);
+    }
+    // there's no mapping elsewhere, so files are always read
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    let _ = mmap;
+
+    search(
+        printer,
+        path,
+        LineReader::new(BufReader::new(file)),
+        matcher,
+    )
 }
 
 pub struct Config {
     pub query: String,
-    pub file_path: String,
+    // files, directories to search every file under, or `-` for standard input
+    pub paths: Vec<PathBuf>,
     pub ignore_case: bool,
+    // the query is a regular expression rather than a literal string
+    pub regex: bool,
+    // select the lines that don't match instead
+    pub invert: bool,
+    pub filter: Filter,
+    pub format: Format,
+    // map files into memory rather than reading them
+    pub mmap: bool,
+    // how many files to search at once
+    pub threads: usize,
+    // print files in the order the walk finds them, rather than as they're done
+    pub sort_by_path: bool,
 }
 
 impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-        args.next();
+    pub fn build(args: impl Iterator<Item = String>) -> Result<Config, ArgsError> {
+        let mut args = Args::new(args.skip(1));
+
+        let mut values = Vec::new();
+        let mut ignore_case = None;
+        let mut regex = false;
+        let mut invert = false;
+        let mut mmap = false;
+        let mut threads = 0;
+        let mut sort_by_path = false;
+        let mut color = ColorChoice::default();
+        let mut recursive = false;
+        let mut with_filename = None;
+        let mut filter = Filter::default();
+        let mut format = Format::default();
+
+        while let Some(arg) = args.next_arg()? {
+            let flag = match arg {
+                Arg::Value(value) => {
+                    values.push(value);
+                    continue;
+                }
+                Arg::Flag(flag) => flag,
+            };
+
+            match flag.as_str() {
+                "-i" | "--ignore-case" => ignore_case = Some(true),
+                "--no-ignore-case" => ignore_case = Some(false),
+                "-E" | "--extended-regexp" => regex = true,
+                "-v" | "--invert-match" => invert = true,
+                "-c" | "--count" => format.mode = Mode::Count,
+                "-l" | "--files-with-matches" => format.mode = Mode::FilesWithMatches,
+                "-L" | "--files-without-match" => format.mode = Mode::FilesWithoutMatch,
+                "-q" | "--quiet" | "--silent" => format.mode = Mode::Quiet,
+                "--json" => format.mode = Mode::Json,
+                "-m" | "--max-count" => format.max_count = Some(args.number()?),
+                "-r" | "--recursive" => recursive = true,
+                "--glob" => filter.globs.push(Glob::new(&args.value()?)),
+                "--exclude" => filter.excludes.push(Glob::new(&args.value()?)),
+                "--hidden" => filter.hidden = true,
+                "--mmap" => mmap = true,
+                "--color" | "--colour" => {
+                    let value = args.attached_value().unwrap_or_else(|| "auto".to_string());
+                    color = ColorChoice::parse(&value)
+                        .ok_or(ArgsError::InvalidValue { flag, value })?;
+                }
+                "-j" | "--threads" => threads = args.number()?,
+                "--sort" => {
+                    sort_by_path = match args.value()?.as_str() {
+                        "path" => true,
+                        "none" => false,
+                        value => {
+                            return Err(ArgsError::InvalidValue {
+                                flag,
+                                value: value.to_string(),
+                            })
+                        }
+                    }
+                }
+                "-n" | "--line-number" => format.line_number = true,
+                "-b" | "--byte-offset" => format.byte_offset = true,
+                "-H" | "--with-filename" => with_filename = Some(true),
+                "-h" | "--no-filename" => with_filename = Some(false),
+                "-A" | "--after-context" => format.after = args.number()?,
+                "-B" | "--before-context" => format.before = args.number()?,
+                "-C" | "--context" => {
+                    format.after = args.number()?;
+                    format.before = format.after;
+                }
+                "--help" => return Err(ArgsError::Help),
+                "-V" | "--version" => return Err(ArgsError::Version),
+                _ => return Err(ArgsError::UnknownFlag(flag)),
+            }
+        }
+
+        let mut values = values.into_iter();
+        let query = values.next().ok_or(ArgsError::MissingQuery)?;
+        let mut paths: Vec<PathBuf> = values.map(PathBuf::from).collect();
+
+        if paths.is_empty() {
+            paths.push(PathBuf::from(if recursive { "." } else { "-" }));
+        }
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
+        // JSON is for programs, which want no escape codes in it
+        format.colors = color.colors().filter(|_| format.mode != Mode::Json);
+        // one file on its own is the only time the file names go without saying
+        format.with_filename = with_filename
+            .unwrap_or_else(|| paths.len() > 1 || paths.iter().any(|path| path.is_dir()));
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
-        };
+        if threads == 0 {
+            threads = thread::available_parallelism().map_or(1, |n| n.get());
+        }
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // the flags win over the environment
+        let ignore_case = ignore_case.unwrap_or_else(|| env::var("IGNORE_CASE").is_ok());
 
         Ok(Config {
             query,
-            file_path,
+            paths,
             ignore_case,
+            regex,
+            invert,
+            filter,
+            format,
+            mmap,
+            threads,
+            sort_by_path,
         })
     }
 }
 
 pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    // let mut results = Vec::new();
-
-    // for line in contents.lines() {
-    //     if line.contains(query) {
-    //         results.push(line);
-    //     }
-    // }
-
-    // results
-
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
+    let matcher = Matcher::literal(query, false, false);
+    matcher
+        .find_matches(contents)
+        .map(|found| found.line)
         .collect()
 }
 
 pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
-    let mut results = Vec::new();
-
-    for line in contents.lines() {
-        if line.to_lowercase().contains(&query) {
-            results.push(line);
-        }
-    }
-
-    results
+    let matcher = Matcher::literal(query, true, false);
+    matcher
+        .find_matches(contents)
+        .map(|found| found.line)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn build(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::build(["minigrep"].iter().chain(args).map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn flags() {
+        let config = build(&["-inr", "-C1", "--glob=*.rs", "--", "-query"]).unwrap();
+        assert!(config.ignore_case && config.format.line_number);
+        assert_eq!((1, 1), (config.format.before, config.format.after));
+        assert_eq!("-query", config.query);
+        assert_eq!(vec![PathBuf::from(".")], config.paths);
+        assert!(config.format.with_filename);
+
+        assert!(!build(&["--no-ignore-case", "q", "f"]).unwrap().ignore_case);
+        assert_eq!(
+            Some(ArgsError::UnknownFlag("-x".to_string())),
+            build(&["-nx", "q", "f"]).err()
+        );
+        assert_eq!(vec![PathBuf::from("-")], build(&["q"]).unwrap().paths);
+        assert_eq!(Some(ArgsError::Help), build(&["q", "--help"]).err());
+    }
+
     #[test]
     fn one_result() {
         let query = "duct";
@@ -118,5 +372,27 @@ Trust me.";
             vec!["Rust:", "Trust me."],
             search_case_insensitive(query, contents)
         );
+
+        // however long, a literal query is never too large to search for
+        let query = "ab".repeat(60_000);
+        let contents = format!("x\n{}", query.to_uppercase());
+        assert_eq!(1, search_case_insensitive(&query, &contents).len());
+    }
+
+    #[test]
+    fn regex() {
+        let matcher = Matcher::new(r"^\w+:$|f[a-z]+t", true, false, false).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive."],
+            matcher
+                .find_matches(contents)
+                .map(|found| found.line)
+                .collect::<Vec<_>>()
+        );
     }
 }
@@ -0,0 +1,206 @@
+// reads the lines of a file one at a time, so a file is never held in memory
+// whole, except through a memory map, which the OS pages in and out as it likes
+
+use std::io::{self, BufRead};
+
+/// A source of lines, each lent out until the next is asked for.
+pub trait Lines {
+    /// The next line without its `\n` or `\r\n`, and the byte offset it starts at.
+    fn next_line(&mut self) -> io::Result<Option<(u64, &[u8])>>;
+}
+
+fn trim_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Whether the start of a file looks like binary rather than text, going by
+/// whether it has a NUL byte, as grep does.
+pub fn looks_binary(start: &[u8]) -> bool {
+    start[..start.len().min(8192)].contains(&0)
+}
+
+/// The lines of a reader, read into one buffer that's reused for every line.
+pub struct LineReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    offset: u64,
+}
+
+impl<R: BufRead> LineReader<R> {
+    pub fn new(reader: R) -> LineReader<R> {
+        LineReader {
+            reader,
+            buf: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Whether what's in the reader's buffer so far looks binary.
+    pub fn looks_binary(&mut self) -> io::Result<bool> {
+        Ok(looks_binary(self.reader.fill_buf()?))
+    }
+}
+
+impl<R: BufRead> Lines for LineReader<R> {
+    fn next_line(&mut self) -> io::Result<Option<(u64, &[u8])>> {
+        self.buf.clear();
+        let read = self.reader.read_until(b'\n', &mut self.buf)?;
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let start = self.offset;
+        self.offset += read as u64;
+        Ok(Some((start, trim_end(&self.buf))))
+    }
+}
+
+/// The lines of bytes already in memory, lent out without copying.
+pub struct SliceLines<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl SliceLines<'_> {
+    pub fn new(bytes: &[u8]) -> SliceLines<'_> {
+        SliceLines { bytes, pos: 0 }
+    }
+}
+
+impl Lines for SliceLines<'_> {
+    fn next_line(&mut self) -> io::Result<Option<(u64, &[u8])>> {
+        let rest = &self.bytes[self.pos..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        let start = self.pos;
+        self.pos += len;
+        Ok(Some((start as u64, trim_end(&rest[..len]))))
+    }
+}
+
+/// A file mapped into memory, read only.
+///
+/// If another process truncates the file while it's mapped, reading the missing
+/// part kills this one with `SIGBUS`, which is why mapping is opt-in. It's only
+/// on 64-bit Unix; files are read instead everywhere else.
+#[cfg(all(unix, target_pointer_width = "64"))]
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Mmap {
+    pub fn open(file: &std::fs::File) -> io::Result<Mmap> {
+        use std::os::fd::AsRawFd;
+
+        const PROT_READ: i32 = 1;
+        const MAP_PRIVATE: i32 = 2;
+        const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+        // `off` is an `off_t`, which is only sure to be 64 bits on 64-bit targets
+        extern "C" {
+            fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+                -> *mut u8;
+        }
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::OutOfMemory, "file too large to map"))?;
+        // mapping nothing is an error, but an empty file is fine
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        // SAFETY: a fresh private read-only mapping aliases nothing in this process
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl std::ops::Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes until dropped
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        extern "C" {
+            fn munmap(addr: *mut u8, len: usize) -> i32;
+        }
+
+        if self.len > 0 {
+            // SAFETY: unmaps exactly what `open` mapped, which nothing borrows now
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(mut lines: impl Lines) -> Vec<(u64, String)> {
+        let mut all = Vec::new();
+        while let Some((offset, line)) = lines.next_line().unwrap() {
+            all.push((offset, String::from_utf8_lossy(line).into_owned()));
+        }
+        all
+    }
+
+    #[test]
+    fn readers_and_slices_split_alike() {
+        let text = b"one\r\ntwo\n\nthree";
+        let expected = vec![
+            (0, "one".to_string()),
+            (5, "two".to_string()),
+            (9, String::new()),
+            (10, "three".to_string()),
+        ];
+
+        // a tiny buffer, so lines span several reads
+        let reader = io::BufReader::with_capacity(2, &text[..]);
+        assert_eq!(expected, collect(LineReader::new(reader)));
+        assert_eq!(expected, collect(SliceLines::new(text)));
+    }
+
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    #[test]
+    fn maps_files() {
+        let path = std::env::temp_dir().join(format!("io_project_mmap_{}", std::process::id()));
+        std::fs::write(&path, "mapped\nlines\n").unwrap();
+
+        let map = Mmap::open(&std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(b"mapped\nlines\n", &map[..]);
+        assert_eq!(2, collect(SliceLines::new(&map)).len());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
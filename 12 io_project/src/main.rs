@@ -1,3 +1,4 @@
+use io_project::args::{self, ArgsError};
 use io_project::Config;
 use std::env;
 
@@ -8,14 +9,26 @@ fn main() {
     //dbg!(args);
 
     let config = Config::build(env::args()).unwrap_or_else(|err| {
-        // prints to stderr
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        match err {
+            ArgsError::Help => print!("{}", args::HELP),
+            ArgsError::Version => println!("{}", args::VERSION),
+            err => {
+                // prints to stderr
+                eprintln!("Problem parsing arguments: {err}");
+                process::exit(2);
+            }
+        }
+        process::exit(0);
     });
 
-    if let Err(e) = io_project::run(config) {
-        // prints to stderr
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    // the same exit codes as grep: 0 for a match, 1 for none, 2 for trouble
+    match io_project::run(config) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            // prints to stderr
+            eprintln!("Application error: {e}");
+            process::exit(2);
+        }
     }
 }
@@ -0,0 +1,207 @@
+// decides which lines are selected, and where in a line the query matches
+
+use crate::regex::{self, Regex, RegexError};
+use std::iter::Enumerate;
+use std::ops::Range;
+use std::str::Lines;
+
+enum Kind {
+    Literal(String),
+    // a literal query that ignores case, matched a char at a time: lowercasing
+    // the line would move the matches around, and as a pattern a long query
+    // could be too large to compile
+    Folded(Vec<char>),
+    Regex(Regex),
+}
+
+/// The query, compiled once for every line of every file.
+pub struct Matcher {
+    kind: Kind,
+    // select the lines that don't match instead
+    invert: bool,
+}
+
+impl Matcher {
+    pub fn new(
+        query: &str,
+        regex: bool,
+        ignore_case: bool,
+        invert: bool,
+    ) -> Result<Matcher, RegexError> {
+        let kind = match (regex, ignore_case) {
+            (false, _) => return Ok(Matcher::literal(query, ignore_case, invert)),
+            (true, false) => Kind::Regex(Regex::new(query)?),
+            (true, true) => Kind::Regex(Regex::case_insensitive(query)?),
+        };
+        Ok(Matcher { kind, invert })
+    }
+
+    /// A matcher for `query` as plain text, which has nothing in it that could
+    /// fail to compile.
+    pub fn literal(query: &str, ignore_case: bool, invert: bool) -> Matcher {
+        let kind = if ignore_case {
+            Kind::Folded(query.chars().collect())
+        } else {
+            Kind::Literal(query.to_string())
+        };
+        Matcher { kind, invert }
+    }
+
+    /// Whether the line is one to print: one that matches, or with `-v`, one
+    /// that doesn't.
+    pub fn selects(&self, line: &str) -> bool {
+        self.is_match(line) != self.invert
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match &self.kind {
+            Kind::Literal(query) => line.contains(query.as_str()),
+            Kind::Folded(_) => self.find_at(line, 0).is_some(),
+            Kind::Regex(regex) => regex.is_match(line),
+        }
+    }
+
+    /// The byte range of the first match at or after byte `start`.
+    pub fn find_at(&self, line: &str, start: usize) -> Option<Range<usize>> {
+        match &self.kind {
+            Kind::Literal(query) => line[start..]
+                .find(query.as_str())
+                .map(|i| start + i..start + i + query.len()),
+            Kind::Folded(query) => (start..=line.len())
+                .filter(|&i| line.is_char_boundary(i))
+                .find_map(|i| Some(i..i + folded_prefix(query, &line[i..])?)),
+            Kind::Regex(regex) => regex.find_at(line, start),
+        }
+    }
+
+    /// The byte ranges of every match in `line`, left to right, none overlapping.
+    pub fn spans(&self, line: &str) -> Vec<Range<usize>> {
+        match self.find_at(line, 0) {
+            Some(first) => self.spans_from(line, first),
+            None => Vec::new(),
+        }
+    }
+
+    fn spans_from(&self, line: &str, first: Range<usize>) -> Vec<Range<usize>> {
+        let mut spans = Vec::new();
+        let mut next = Some(first);
+        while let Some(span) = next {
+            let start = if !span.is_empty() {
+                let end = span.end;
+                spans.push(span);
+                end
+            } else {
+                // an empty match would be found again forever, so move past it
+                match line[span.end..].chars().next() {
+                    Some(c) => span.end + c.len_utf8(),
+                    None => break,
+                }
+            };
+            next = self.find_at(line, start);
+        }
+        spans
+    }
+
+    /// The line as a [`Match`] if it's selected, with `line_number` counting
+    /// from 1.
+    pub fn match_line<'a>(&self, line_number: usize, line: &'a str) -> Option<Match<'a>> {
+        let first = self.find_at(line, 0);
+        if first.is_some() == self.invert {
+            return None;
+        }
+
+        Some(Match {
+            line_number,
+            line,
+            spans: first.map_or_else(Vec::new, |first| self.spans_from(line, first)),
+        })
+    }
+
+    /// The selected lines of `contents`, found one at a time as the iterator is
+    /// advanced.
+    pub fn find_matches<'m, 'a>(&'m self, contents: &'a str) -> Matches<'m, 'a> {
+        Matches {
+            matcher: self,
+            lines: contents.lines().enumerate(),
+        }
+    }
+}
+
+// the length in bytes of the start of `text` that is `query` but for case
+fn folded_prefix(query: &[char], text: &str) -> Option<usize> {
+    let mut chars = text.char_indices();
+    for &q in query {
+        let (_, c) = chars.next()?;
+        if c != q && !regex::case_variants(q).any(|v| v == c) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(text.len(), |(i, _)| i))
+}
+
+/// A selected line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    pub line_number: usize,
+    pub line: &'a str,
+    // the byte ranges of the matches in `line`; none for a line selected by `-v`
+    pub spans: Vec<Range<usize>>,
+}
+
+/// The iterator returned by [`Matcher::find_matches`].
+pub struct Matches<'m, 'a> {
+    matcher: &'m Matcher,
+    lines: Enumerate<Lines<'a>>,
+}
+
+impl<'a> Iterator for Matches<'_, 'a> {
+    type Item = Match<'a>;
+
+    fn next(&mut self) -> Option<Match<'a>> {
+        let matcher = self.matcher;
+        self.lines
+            .find_map(|(index, line)| matcher.match_line(index + 1, line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_every_span() {
+        let matcher = Matcher::new("a.", false, true, false).unwrap();
+        assert_eq!(vec![4..6, 7..9], matcher.spans("abc A. a.!"));
+
+        let matcher = Matcher::new("é", false, true, false).unwrap();
+        assert_eq!(vec![0..2, 3..5], matcher.spans("É é"));
+        let matcher = Matcher::new("", false, true, false).unwrap();
+        assert!(matcher.selects("a") && matcher.spans("a").is_empty());
+
+        let matcher = Matcher::new(r"\d*", true, false, false).unwrap();
+        assert_eq!(vec![1..3, 4..5], matcher.spans("x12y3"));
+
+        let matcher = Matcher::new("x", false, false, true).unwrap();
+        assert!(matcher.selects("y") && !matcher.selects("x"));
+    }
+
+    #[test]
+    fn iterates_over_matches() {
+        let matcher = Matcher::new("o", false, false, false).unwrap();
+        let mut matches = matcher.find_matches("oboe\nthree\ntwo\nfoo");
+
+        let first = matches.next().unwrap();
+        assert_eq!((1, "oboe"), (first.line_number, first.line));
+        assert_eq!(vec![0..1, 2..3], first.spans);
+        assert_eq!(3, matches.next().unwrap().line_number);
+        assert_eq!(vec![1..2, 2..3], matches.next().unwrap().spans);
+        assert_eq!(None, matches.next());
+
+        // an inverted match has no spans to highlight
+        let inverted = Matcher::new("o", false, false, true).unwrap();
+        let found: Vec<_> = inverted.find_matches("one\nthree").collect();
+        assert_eq!(1, found.len());
+        assert_eq!((2, "three"), (found[0].line_number, found[0].line));
+        assert!(found[0].spans.is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+// prints matching lines the way grep does: `path:line:offset:text` for a match,
+// with `-` in place of `:` for a context line, and `--` between groups of lines
+// that aren't next to each other
+
+use crate::color::{self, Colors};
+use crate::json;
+use crate::lines::Lines;
+use crate::matcher::Matcher;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// What gets printed for each file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Mode {
+    // the matching lines
+    #[default]
+    Lines,
+    // how many lines match
+    Count,
+    // the file's name, if any line matches
+    FilesWithMatches,
+    // the file's name, if none does
+    FilesWithoutMatch,
+    // nothing at all
+    Quiet,
+    // the matching lines with their context, as JSON events
+    Json,
+}
+
+/// What gets printed, and what goes around each printed line.
+#[derive(Debug, Clone, Default)]
+pub struct Format {
+    pub mode: Mode,
+    // stop reading a file after this many matches
+    pub max_count: Option<usize>,
+    pub line_number: bool,
+    // of the start of the line, from the start of the file
+    pub byte_offset: bool,
+    pub with_filename: bool,
+    // context lines before and after each match
+    pub before: usize,
+    pub after: usize,
+    // `None` for plain text
+    pub colors: Option<Colors>,
+}
+
+impl Format {
+    /// Whether groups of lines get `--` between them, which is only with context.
+    pub fn separates_groups(&self) -> bool {
+        self.mode == Mode::Lines && (self.before > 0 || self.after > 0)
+    }
+
+    /// The `--` between groups of lines, colored if need be.
+    pub fn group_separator(&self) -> String {
+        self.paint(|colors| &colors.separator, "--")
+    }
+
+    // `text` in the color `part` picks, if the output is colored at all
+    fn paint(&self, part: impl Fn(&Colors) -> &str, text: &str) -> String {
+        match &self.colors {
+            Some(colors) => color::paint(part(colors), text),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// What's been found so far, counted as lines are printed, so only in the
+/// modes that print them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub files_searched: usize,
+    pub files_matched: usize,
+    pub matched_lines: usize,
+    // the matches within those lines
+    pub matches: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.files_searched += other.files_searched;
+        self.files_matched += other.files_matched;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+pub struct Printer<W: Write> {
+    format: Format,
+    out: W,
+    // whether any line has been printed yet, from any file, so the first group
+    // gets no separator
+    printed: bool,
+    // whether any line has been printed from the current file
+    printed_file: bool,
+    matched: bool,
+    // for every file so far, and for the current one
+    stats: Stats,
+    file_stats: Stats,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(format: Format, out: W) -> Printer<W> {
+        Printer {
+            format,
+            out,
+            printed: false,
+            printed_file: false,
+            matched: false,
+            stats: Stats::default(),
+            file_stats: Stats::default(),
+        }
+    }
+
+    /// The output written so far, for a printer that writes to memory.
+    pub fn into_output(self) -> W {
+        self.out
+    }
+
+    /// Whether any file so far was what the mode looks for: one with a match, or
+    /// with `-L`, one without.
+    pub fn matched(&self) -> bool {
+        self.matched
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Prints what the mode calls for about the `lines` the `matcher` selects.
+    /// Lines that aren't UTF-8 are matched with U+FFFD in place of the invalid
+    /// bytes, but printed as they are, and never highlighted.
+    pub fn print_file(
+        &mut self,
+        path: &Path,
+        lines: &mut impl Lines,
+        matcher: &Matcher,
+    ) -> io::Result<()> {
+        let mode = self.format.mode;
+        self.printed_file = false;
+        self.file_stats = Stats::default();
+        let found = match mode {
+            Mode::Lines | Mode::Json => self.print_lines(path, lines, matcher)?,
+            Mode::Count => count(lines, matcher, self.format.max_count)?,
+            // one match settles it
+            Mode::FilesWithMatches | Mode::FilesWithoutMatch | Mode::Quiet => {
+                let limit = self.format.max_count.unwrap_or(1).min(1);
+                count(lines, matcher, Some(limit))?
+            }
+        };
+
+        let name = self
+            .format
+            .paint(|colors| &colors.filename, &path.display().to_string());
+        match mode {
+            Mode::Count if self.format.with_filename => {
+                let separator = self.format.paint(|colors| &colors.separator, ":");
+                writeln!(self.out, "{name}{separator}{found}")?
+            }
+            Mode::Count => writeln!(self.out, "{found}")?,
+            Mode::FilesWithMatches if found > 0 => writeln!(self.out, "{name}")?,
+            Mode::FilesWithoutMatch if found == 0 => writeln!(self.out, "{name}")?,
+            Mode::Json if self.printed_file => {
+                writeln!(self.out, "{}", json::end(path, &self.file_stats))?
+            }
+            _ => {}
+        }
+
+        self.file_stats.files_searched = 1;
+        self.file_stats.files_matched = usize::from(found > 0);
+        self.stats.add(&self.file_stats);
+
+        self.matched |= match mode {
+            Mode::FilesWithoutMatch => found == 0,
+            _ => found > 0,
+        };
+        Ok(())
+    }
+
+    // prints the matching lines with their context, returning how many there were
+    fn print_lines(
+        &mut self,
+        path: &Path,
+        lines: &mut impl Lines,
+        matcher: &Matcher,
+    ) -> io::Result<usize> {
+        let max_count = self.format.max_count.unwrap_or(usize::MAX);
+        let mut found = 0;
+        let context = self.format.separates_groups();
+        // (line index, byte offset, line), for the lines that could still be
+        // context before a match; the buffers of lines that drop out are reused
+        let mut before: VecDeque<(usize, u64, Vec<u8>)> =
+            VecDeque::with_capacity(self.format.before);
+        let mut spare = Vec::new();
+        let mut last_printed = None;
+        let mut after_left = 0;
+
+        for index in 0.. {
+            let Some((offset, line)) = lines.next_line()? else {
+                break;
+            };
+
+            let text = String::from_utf8_lossy(line);
+            if found == max_count {
+                // the rest of the last match's context, then stop reading
+                if after_left == 0 {
+                    break;
+                }
+                let spans = self.context_spans(matcher, line);
+                self.print_line(path, index, offset, line, '-', &spans)?;
+                after_left -= 1;
+            } else if let Some(selected) = matcher.match_line(index + 1, &text) {
+                found += 1;
+                let first = before.front().map_or(index, |&(index, _, _)| index);
+                let adjacent = last_printed.is_some_and(|last| last + 1 == first);
+                if context && self.printed && !adjacent {
+                    writeln!(self.out, "{}", self.format.group_separator())?;
+                }
+
+                while let Some((index, offset, line)) = before.pop_front() {
+                    let spans = self.context_spans(matcher, &line);
+                    self.print_line(path, index, offset, &line, '-', &spans)?;
+                    spare = line;
+                }
+                // spans found with U+FFFD in place of bad bytes don't fit the bytes
+                let spans = match text {
+                    Cow::Borrowed(_) => &selected.spans[..],
+                    Cow::Owned(_) => &[],
+                };
+                self.file_stats.matched_lines += 1;
+                self.file_stats.matches += spans.len();
+                self.print_line(path, index, offset, line, ':', spans)?;
+                last_printed = Some(index);
+                after_left = self.format.after;
+            } else if after_left > 0 {
+                let spans = self.context_spans(matcher, line);
+                self.print_line(path, index, offset, line, '-', &spans)?;
+                last_printed = Some(index);
+                after_left -= 1;
+            } else if self.format.before > 0 {
+                if before.len() == self.format.before {
+                    if let Some((_, _, line)) = before.pop_front() {
+                        spare = line;
+                    }
+                }
+                let mut copy = std::mem::take(&mut spare);
+                copy.clear();
+                copy.extend_from_slice(line);
+                before.push_back((index, offset, copy));
+            }
+        }
+
+        Ok(found)
+    }
+
+    // where the matches are in a context line, which is only worth finding out
+    // if there are colors to highlight them with, or JSON to list them in
+    fn context_spans(&self, matcher: &Matcher, line: &[u8]) -> Vec<Range<usize>> {
+        let wanted = self.format.colors.is_some() || self.format.mode == Mode::Json;
+        match std::str::from_utf8(line) {
+            Ok(line) if wanted => matcher.spans(line),
+            _ => Vec::new(),
+        }
+    }
+
+    // `spans` are the byte ranges in `line` to highlight as matches
+    #[allow(clippy::too_many_arguments)]
+    fn print_line(
+        &mut self,
+        path: &Path,
+        index: usize,
+        offset: u64,
+        line: &[u8],
+        separator: char,
+        spans: &[Range<usize>],
+    ) -> io::Result<()> {
+        let first_in_file = !self.printed_file;
+        self.printed = true;
+        self.printed_file = true;
+        let selected = separator == ':';
+        let format = &self.format;
+
+        if format.mode == Mode::Json {
+            if first_in_file {
+                writeln!(self.out, "{}", json::begin(path))?;
+            }
+            let event = json::line(path, index + 1, offset, line, selected, spans);
+            return writeln!(self.out, "{event}");
+        }
+        let separator = format.paint(|colors| &colors.separator, &separator.to_string());
+
+        if format.with_filename {
+            let name = format.paint(|colors| &colors.filename, &path.display().to_string());
+            write!(self.out, "{name}{separator}")?;
+        }
+        if format.line_number {
+            let number = format.paint(|colors| &colors.line_number, &(index + 1).to_string());
+            write!(self.out, "{number}{separator}")?;
+        }
+        if format.byte_offset {
+            let offset = format.paint(|colors| &colors.byte_offset, &offset.to_string());
+            write!(self.out, "{offset}{separator}")?;
+        }
+
+        match (&format.colors, std::str::from_utf8(line)) {
+            (Some(colors), Ok(line)) => {
+                let (match_color, line_color) = match selected {
+                    true => (&colors.selected_match, &colors.selected_line),
+                    false => (&colors.context_match, &colors.context_line),
+                };
+
+                let mut last = 0;
+                for span in spans {
+                    write!(
+                        self.out,
+                        "{}",
+                        color::paint(line_color, &line[last..span.start])
+                    )?;
+                    write!(
+                        self.out,
+                        "{}",
+                        color::paint(match_color, &line[span.clone()])
+                    )?;
+                    last = span.end;
+                }
+                write!(self.out, "{}", color::paint(line_color, &line[last..]))?;
+            }
+            _ => self.out.write_all(line)?,
+        }
+        writeln!(self.out)
+    }
+}
+
+// how many of the `lines` match, counting no further than `limit`
+fn count(lines: &mut impl Lines, matcher: &Matcher, limit: Option<usize>) -> io::Result<usize> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut found = 0;
+    while found < limit {
+        match lines.next_line()? {
+            Some((_, line)) if matcher.selects(&String::from_utf8_lossy(line)) => found += 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::SliceLines;
+
+    fn print(format: Format, contents: &str, query: &str) -> String {
+        print_files(format, &[contents], query)
+    }
+
+    fn print_files(format: Format, files: &[&str], query: &str) -> String {
+        let matcher = Matcher::new(query, false, false, false).unwrap();
+        let mut printer = Printer::new(format, Vec::new());
+        for (i, contents) in files.iter().enumerate() {
+            let path = format!("{}.txt", i + 1);
+            printer
+                .print_file(
+                    Path::new(&path),
+                    &mut SliceLines::new(contents.as_bytes()),
+                    &matcher,
+                )
+                .unwrap();
+        }
+        String::from_utf8(printer.out).unwrap()
+    }
+
+    #[test]
+    fn prefixes_matches_and_context() {
+        let format = Format {
+            line_number: true,
+            byte_offset: true,
+            with_filename: true,
+            before: 1,
+            ..Format::default()
+        };
+        assert_eq!(
+            "1.txt-1-0-one\n1.txt:2:5:two\n",
+            print(format, "one\r\ntwo\nthree\n", "tw")
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_context() {
+        let contents = "a\nx\nb\nc\nx\nd\ne\nf\ng\nx\n";
+        let format = Format {
+            line_number: true,
+            before: 1,
+            after: 1,
+            ..Format::default()
+        };
+        assert_eq!(
+            "1-a\n2:x\n3-b\n4-c\n5:x\n6-d\n--\n9-g\n10:x\n",
+            print(format, contents, "x")
+        );
+    }
+
+    #[test]
+    fn counts_and_lists_files() {
+        let files = ["x\nx\nx\n", "y\n"];
+        let format = |mode, max_count| Format {
+            mode,
+            max_count,
+            with_filename: true,
+            ..Format::default()
+        };
+
+        assert_eq!(
+            "1.txt:3\n2.txt:0\n",
+            print_files(format(Mode::Count, None), &files, "x")
+        );
+        assert_eq!(
+            "1.txt:2\n2.txt:0\n",
+            print_files(format(Mode::Count, Some(2)), &files, "x")
+        );
+        assert_eq!(
+            "1.txt\n",
+            print_files(format(Mode::FilesWithMatches, None), &files, "x")
+        );
+        assert_eq!(
+            "2.txt\n",
+            print_files(format(Mode::FilesWithoutMatch, None), &files, "x")
+        );
+        assert_eq!("", print_files(format(Mode::Quiet, None), &files, "x"));
+    }
+
+    #[test]
+    fn stops_after_max_count_with_trailing_context() {
+        let format = Format {
+            max_count: Some(1),
+            after: 1,
+            ..Format::default()
+        };
+        assert_eq!("x1\nx2\n", print(format, "x1\nx2\nx3\n", "x"));
+    }
+
+    #[test]
+    fn prints_json_events() {
+        let format = Format {
+            mode: Mode::Json,
+            after: 1,
+            ..Format::default()
+        };
+        let expected = [
+            r#"{"type":"begin","path":{"text":"1.txt"}}"#,
+            r#"{"type":"match","path":{"text":"1.txt"},"line_number":2,"byte_offset":2,"line":{"text":"x \"x\""},"submatches":[{"match":{"text":"x"},"start":0,"end":1},{"match":{"text":"x"},"start":3,"end":4}]}"#,
+            r#"{"type":"context","path":{"text":"1.txt"},"line_number":3,"byte_offset":8,"line":{"text":"b"},"submatches":[]}"#,
+            r#"{"type":"end","path":{"text":"1.txt"},"stats":{"matched_lines":1,"matches":2}}"#,
+            "",
+        ];
+        assert_eq!(
+            expected.join("\n"),
+            print_files(format, &["a\nx \"x\"\nb\nc\n", "y\n"], "x")
+        );
+    }
+
+    #[test]
+    fn highlights_matches() {
+        let format = Format {
+            line_number: true,
+            after: 1,
+            colors: Some(Colors::parse("ln=:se=")),
+            ..Format::default()
+        };
+        assert_eq!(
+            "1:a \x1b[01;31m\x1b[Kxx\x1b[m\x1b[K b\n2-c\n",
+            print(format, "a xx b\nc\n", "xx")
+        );
+    }
+}
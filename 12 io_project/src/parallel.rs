@@ -0,0 +1,227 @@
+// searches many files at once: one thread walks the directories into a bounded
+// queue, so a huge tree isn't listed far ahead of the search, workers take files
+// off it and print each into memory, and the calling thread writes out each
+// file's output whole, so the lines of different files never interleave
+
+use crate::output::{Format, Printer, Stats};
+use crate::walk::{Walk, WalkError};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+// files found but not yet taken by a worker, per worker
+const QUEUED_PER_THREAD: usize = 16;
+
+// what searching one file printed, whether it matched and what it found, or why
+// it couldn't be searched
+type Searched = Result<(Vec<u8>, bool, Stats), WalkError>;
+
+/// What a search of many files came to.
+pub struct Summary {
+    pub matched: bool,
+    // files that couldn't be searched, already reported
+    pub failed: usize,
+    pub stats: Stats,
+    // whatever reads the output stopped reading, so the search stopped early
+    pub closed: bool,
+}
+
+/// Searches the files of `walk` on `threads` threads with `search`, writing the
+/// output of each file to `out` as a whole, in walk order with `sorted`, or
+/// else as each file is done. With `stop_at_match`, stops at the first match.
+pub fn search<S>(
+    walk: Walk,
+    threads: usize,
+    sorted: bool,
+    stop_at_match: bool,
+    format: &Format,
+    out: &mut impl Write,
+    search: S,
+) -> io::Result<Summary>
+where
+    S: Fn(&mut Printer<Vec<u8>>, &Path) -> io::Result<()> + Sync,
+{
+    let (paths, queue) = mpsc::sync_channel::<(usize, PathBuf)>(threads * QUEUED_PER_THREAD);
+    // shared by the workers, and dropped with the last of them, which tells the
+    // walk nobody's listening any more
+    let queue = Arc::new(Mutex::new(queue));
+    let (results, searched) = mpsc::channel::<(usize, Searched)>();
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let queue = Arc::clone(&queue);
+            let results = results.clone();
+            let (stop, search) = (&stop, &search);
+            scope.spawn(move || loop {
+                // the lock is only held while waiting, never while searching
+                let next = queue.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                let Ok((index, path)) = next else {
+                    break;
+                };
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let mut printer = Printer::new(format.clone(), Vec::new());
+                let result = match search(&mut printer, &path) {
+                    Ok(()) => {
+                        let (matched, stats) = (printer.matched(), printer.stats());
+                        Ok((printer.into_output(), matched, stats))
+                    }
+                    Err(error) => Err(WalkError { path, error }),
+                };
+                if results.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(queue);
+
+        let stop = &stop;
+        scope.spawn(move || {
+            for (index, path) in walk.enumerate() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let sent = match path {
+                    Ok(path) => paths.send((index, path)).is_ok(),
+                    Err(e) => results.send((index, Err(e))).is_ok(),
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+
+        let mut writer = Writer {
+            out,
+            separator: format.separates_groups().then(|| format.group_separator()),
+            written: false,
+            summary: Summary {
+                matched: false,
+                failed: 0,
+                stats: Stats::default(),
+                closed: false,
+            },
+        };
+        // results that came in ahead of an earlier file, when sorted
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+
+        // returning drops the results' receiver, which stops the workers as soon
+        // as they're done with their current file
+        let stop_now = || stop.store(true, Ordering::Relaxed);
+        let write_all = || -> io::Result<()> {
+            for (index, result) in searched {
+                if !sorted {
+                    writer.write(result).inspect_err(|_| stop_now())?;
+                } else {
+                    waiting.insert(index, result);
+                    while let Some(result) = waiting.remove(&next) {
+                        writer.write(result).inspect_err(|_| stop_now())?;
+                        next += 1;
+                    }
+                }
+
+                if stop_at_match && writer.summary.matched {
+                    stop_now();
+                    break;
+                }
+            }
+            Ok(())
+        };
+        match write_all() {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => writer.summary.closed = true,
+            written => written?,
+        }
+
+        Ok(writer.summary)
+    })
+}
+
+struct Writer<'a, W> {
+    out: &'a mut W,
+    // `--` to put between files, as between groups within one, if any
+    separator: Option<String>,
+    written: bool,
+    summary: Summary,
+}
+
+impl<W: Write> Writer<'_, W> {
+    fn write(&mut self, result: Searched) -> io::Result<()> {
+        match result {
+            Ok((output, matched, stats)) => {
+                self.summary.matched |= matched;
+                self.summary.stats.add(&stats);
+                if output.is_empty() {
+                    return Ok(());
+                }
+                if let Some(separator) = self.separator.as_ref().filter(|_| self.written) {
+                    writeln!(self.out, "{separator}")?;
+                }
+                self.written = true;
+                self.out.write_all(&output)
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                self.summary.failed += 1;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::SliceLines;
+    use crate::matcher::Matcher;
+    use crate::walk::Filter;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn keeps_files_whole_and_in_order() {
+        let root = env::temp_dir().join(format!("io_project_parallel_{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for i in 0..50 {
+            let lines: String = (0..100).map(|line| format!("{i} {line}\n")).collect();
+            fs::write(root.join(format!("{i:02}.txt")), lines).unwrap();
+        }
+
+        let format = Format {
+            with_filename: true,
+            ..Format::default()
+        };
+        let matcher = Matcher::new("", false, false, false).unwrap();
+        let mut out = Vec::new();
+        let summary = search(
+            Walk::new(std::slice::from_ref(&root), Filter::default()),
+            4,
+            true,
+            false,
+            &format,
+            &mut out,
+            |printer, path| {
+                let contents = fs::read(path)?;
+                printer.print_file(path, &mut SliceLines::new(&contents), &matcher)
+            },
+        )
+        .unwrap();
+
+        assert!(summary.matched);
+        let expected: String = (0..50)
+            .flat_map(|i| {
+                let path = root.join(format!("{i:02}.txt"));
+                (0..100).map(move |line| format!("{}:{i} {line}\n", path.display()))
+            })
+            .collect();
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,755 @@
+// a small regular expression engine for `-E`
+// patterns are compiled once into a program for a Thompson NFA, which is run with
+// a Pike VM: every possible path through the pattern is followed at the same time,
+// so a line is searched in time linear in its length, whatever the pattern
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+
+// bounded repetition is compiled by copying, so these keep a pattern like
+// `(a{1000}){1000}` from taking all the memory
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 100_000;
+
+#[derive(Debug, PartialEq)]
+pub struct RegexError {
+    pub message: &'static str,
+    // character offset into the pattern
+    pub position: usize,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid pattern: {} at {}", self.message, self.position)
+    }
+}
+
+impl Error for RegexError {}
+
+/// `text` with a backslash before everything that would mean more than itself
+/// in a pattern, so the pattern matches just `text`.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_punctuation() {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Look {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    // `\d`, or `\D` when negated
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    items: Vec<ClassItem>,
+    negated: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        let hit = |c: char| {
+            self.items.iter().any(|item| match *item {
+                ClassItem::Range(low, high) => low <= c && c <= high,
+                ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+            })
+        };
+        let found = hit(c) || (ignore_case && case_variants(c).any(hit));
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Look(Look),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &'static str) -> RegexError {
+        RegexError {
+            message,
+            position: self.pos,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => Node::Alternate(branches),
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            nodes.push(self.repeat()?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.remove(0),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Node, RegexError> {
+        let mut node = self.atom()?;
+
+        loop {
+            let (min, max) = match self.peek() {
+                Some('{') => match self.bounds()? {
+                    Some(bounds) => bounds,
+                    // not a valid bound, so a literal `{`, which can't repeat anything
+                    None => return Ok(node),
+                },
+                Some(c @ ('*' | '+' | '?')) => {
+                    self.pos += 1;
+                    match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(node),
+            };
+            if matches!(node, Node::Look(_) | Node::Empty) {
+                return Err(self.error("nothing to repeat"));
+            }
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    // parses `{n}`, `{n,}` or `{n,m}`, consuming it; `None`, consuming nothing, if
+    // the brace doesn't start one
+    fn bounds(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+        let start = self.pos;
+        let close = match self.chars[start..].iter().position(|&c| c == '}') {
+            Some(close) => start + close,
+            None => return Ok(None),
+        };
+        let inside: String = self.chars[start + 1..close].iter().collect();
+
+        let number = |s: &str| -> Option<u32> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                None
+            } else {
+                Some(s.parse().unwrap_or(u32::MAX))
+            }
+        };
+        let (min, max) = match inside.split_once(',') {
+            None => match number(&inside) {
+                Some(n) => (n, Some(n)),
+                None => return Ok(None),
+            },
+            Some((min, "")) => match number(min) {
+                Some(min) => (min, None),
+                None => return Ok(None),
+            },
+            Some((min, max)) => match (number(min), number(max)) {
+                (Some(min), Some(max)) => (min, Some(max)),
+                _ => return Ok(None),
+            },
+        };
+
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("repetition bounds out of order"));
+        }
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return Err(self.error("repetition bound too large"));
+        }
+        self.pos = close + 1;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        match c {
+            '(' => {
+                self.pos += 1;
+                // groups don't capture, so `(?:...)` means the same as `(...)`
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let node = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(self.error("missing )"));
+                }
+                Ok(node)
+            }
+            ')' => Err(self.error("unmatched )")),
+            '*' | '+' | '?' => Err(self.error("nothing to repeat")),
+            '[' => {
+                self.pos += 1;
+                self.class().map(Node::Class)
+            }
+            '.' => {
+                self.pos += 1;
+                Ok(Node::Any)
+            }
+            '^' => {
+                self.pos += 1;
+                Ok(Node::Look(Look::Start))
+            }
+            '$' => {
+                self.pos += 1;
+                Ok(Node::Look(Look::End))
+            }
+            '\\' => {
+                self.pos += 1;
+                self.escape()
+            }
+            c => {
+                self.pos += 1;
+                Ok(Node::Literal(c))
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, RegexError> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("trailing backslash"))?;
+        self.pos += 1;
+
+        let perl = |perl, negated| {
+            Node::Class(Class {
+                items: vec![ClassItem::Perl(perl, negated)],
+                negated: false,
+            })
+        };
+        Ok(match c {
+            'd' => perl(Perl::Digit, false),
+            'D' => perl(Perl::Digit, true),
+            'w' => perl(Perl::Word, false),
+            'W' => perl(Perl::Word, true),
+            's' => perl(Perl::Space, false),
+            'S' => perl(Perl::Space, true),
+            'b' => Node::Look(Look::WordBoundary),
+            'B' => Node::Look(Look::NotWordBoundary),
+            c => Node::Literal(self.escaped_char(c)?),
+        })
+    }
+
+    // the character an escape other than a class or assertion stands for
+    fn escaped_char(&self, c: char) -> Result<char, RegexError> {
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            c if c.is_ascii_alphanumeric() => Err(RegexError {
+                message: "unknown escape",
+                position: self.pos - 1,
+            }),
+            c => Ok(c),
+        }
+    }
+
+    fn class(&mut self) -> Result<Class, RegexError> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+
+        // a `]` right at the start is a literal
+        if self.eat(']') {
+            items.push(ClassItem::Range(']', ']'));
+        }
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("missing ]"))?;
+            self.pos += 1;
+
+            let low = match c {
+                ']' => break,
+                '\\' => {
+                    let e = self.peek().ok_or_else(|| self.error("missing ]"))?;
+                    self.pos += 1;
+                    match e {
+                        'd' | 'D' | 'w' | 'W' | 's' | 'S' => {
+                            let perl = match e.to_ascii_lowercase() {
+                                'd' => Perl::Digit,
+                                'w' => Perl::Word,
+                                _ => Perl::Space,
+                            };
+                            items.push(ClassItem::Perl(perl, e.is_ascii_uppercase()));
+                            continue;
+                        }
+                        e => self.escaped_char(e)?,
+                    }
+                }
+                c => c,
+            };
+
+            // a `-` at either end is a literal
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            if !is_range {
+                items.push(ClassItem::Range(low, low));
+                continue;
+            }
+            self.pos += 1;
+
+            let high = match self.peek() {
+                Some('\\') => {
+                    self.pos += 1;
+                    let e = self.peek().ok_or_else(|| self.error("missing ]"))?;
+                    self.pos += 1;
+                    self.escaped_char(e)?
+                }
+                Some(c) => {
+                    self.pos += 1;
+                    c
+                }
+                None => return Err(self.error("missing ]")),
+            };
+            if high < low {
+                return Err(self.error("character range out of order"));
+            }
+            items.push(ClassItem::Range(low, high));
+        }
+
+        Ok(Class { items, negated })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Look(Look),
+    // try the first branch before the second
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, RegexError> {
+        if self.program.len() == MAX_PROGRAM {
+            return Err(RegexError {
+                message: "pattern too large",
+                position: 0,
+            });
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), RegexError> {
+        match node {
+            Node::Empty => {}
+            Node::Literal(c) => {
+                self.emit(Inst::Char(*c))?;
+            }
+            Node::Any => {
+                self.emit(Inst::Any)?;
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()))?;
+            }
+            Node::Look(look) => {
+                self.emit(Inst::Look(*look))?;
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i == branches.len() - 1 {
+                        self.compile(branch)?;
+                        break;
+                    }
+                    let split = self.emit(Inst::Split(0, 0))?;
+                    self.compile(branch)?;
+                    jumps.push(self.emit(Inst::Jump(0))?);
+                    self.program[split] = Inst::Split(split + 1, self.program.len());
+                }
+                let end = self.program.len();
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat { node, min, max } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(node)?;
+                        self.emit(Inst::Jump(split))?;
+                        self.program[split] = Inst::Split(split + 1, self.program.len());
+                    }
+                    Some(max) => {
+                        // each optional copy can skip all the ones after it
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Split(0, 0))?);
+                            self.compile(node)?;
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.program[split] = Inst::Split(split + 1, end);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A compiled pattern.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        Regex::build(pattern, false)
+    }
+
+    /// A pattern whose letters match either case.
+    pub fn case_insensitive(pattern: &str) -> Result<Regex, RegexError> {
+        Regex::build(pattern, true)
+    }
+
+    fn build(pattern: &str, ignore_case: bool) -> Result<Regex, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let node = parser.alternation()?;
+        // the only way to stop before the end is an unmatched `)`
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unmatched )"));
+        }
+
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler.compile(&node)?;
+        compiler.emit(Inst::Match)?;
+
+        Ok(Regex {
+            program: compiler.program,
+            ignore_case,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find(text).is_some()
+    }
+
+    /// The byte range of the leftmost match in `text`, preferring earlier
+    /// alternatives and longer repetitions the way Perl does.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        self.find_at(text, 0)
+    }
+
+    /// Like [`Regex::find`], but starting at byte `start`, which must be on a char
+    /// boundary. Anchors and `\b` still see the text before `start`.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Range<usize>> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut matched = None;
+
+        let mut pos = start;
+        let mut prev = text[..start].chars().next_back();
+        let mut chars = text[start..].chars();
+        let mut cur = chars.clone().next();
+
+        loop {
+            // a match starting here is worse than one starting earlier, so this
+            // thread comes last, and none start once a match is found
+            if matched.is_none() {
+                self.add(&mut current, 0, pos, prev, cur, text.len(), pos);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+
+            chars.next();
+            let following = chars.clone().next();
+            let next_pos = pos + cur.map_or(0, char::len_utf8);
+
+            for i in 0..current.list.len() {
+                let (pc, started) = current.list[i];
+                let consumed = match &self.program[pc] {
+                    Inst::Match => {
+                        matched = Some(started..pos);
+                        // every thread after this one has lower priority
+                        break;
+                    }
+                    Inst::Char(c) => cur.is_some_and(|cur| self.same_char(*c, cur)),
+                    Inst::Any => cur.is_some(),
+                    Inst::Class(class) => {
+                        cur.is_some_and(|cur| class.matches(cur, self.ignore_case))
+                    }
+                    _ => false,
+                };
+                if consumed {
+                    self.add(
+                        &mut next,
+                        pc + 1,
+                        next_pos,
+                        cur,
+                        following,
+                        text.len(),
+                        started,
+                    );
+                }
+            }
+
+            if cur.is_none() {
+                break;
+            }
+            pos = next_pos;
+            prev = cur;
+            cur = following;
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched
+    }
+
+    fn same_char(&self, pattern: char, c: char) -> bool {
+        pattern == c || (self.ignore_case && case_variants(c).any(|v| v == pattern))
+    }
+
+    // adds the thread at `pc` and everything reachable from it without consuming
+    // input, in priority order; `prev` and `cur` are the chars around `pos`
+    #[allow(clippy::too_many_arguments)]
+    fn add(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        pos: usize,
+        prev: Option<char>,
+        cur: Option<char>,
+        end: usize,
+        started: usize,
+    ) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if !threads.visit(pc) {
+                continue;
+            }
+            match &self.program[pc] {
+                Inst::Jump(to) => stack.push(*to),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Look(look) => {
+                    let holds = match look {
+                        Look::Start => pos == 0,
+                        Look::End => pos == end,
+                        Look::WordBoundary => prev.is_some_and(is_word) != cur.is_some_and(is_word),
+                        Look::NotWordBoundary => {
+                            prev.is_some_and(is_word) == cur.is_some_and(is_word)
+                        }
+                    };
+                    if holds {
+                        stack.push(pc + 1);
+                    }
+                }
+                _ => threads.list.push((pc, started)),
+            }
+        }
+    }
+}
+
+// the threads of one step, with where each one's match started
+struct Threads {
+    list: Vec<(usize, usize)>,
+    // a program counter was visited this step if its entry equals `generation`
+    visited: Vec<u32>,
+    generation: u32,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            list: Vec::new(),
+            visited: vec![0; len],
+            generation: 1,
+        }
+    }
+
+    fn visit(&mut self, pc: usize) -> bool {
+        if self.visited[pc] == self.generation {
+            return false;
+        }
+        self.visited[pc] = self.generation;
+        true
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.generation += 1;
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The single-char other cases of `c`, e.g. 'A' for 'a'.
+pub fn case_variants(c: char) -> impl Iterator<Item = char> {
+    fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+        let first = chars.next()?;
+        chars.next().is_none().then_some(first)
+    }
+    let lower = single(c.to_lowercase());
+    let upper = single(c.to_uppercase());
+    [lower, upper]
+        .into_iter()
+        .flatten()
+        .filter(move |&v| v != c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+        Regex::new(pattern)
+            .unwrap()
+            .find(text)
+            .map(|range| &text[range])
+    }
+
+    #[test]
+    fn matches_like_perl() {
+        assert_eq!(Some("abbb"), find("ab*", "xabbbc"));
+        assert_eq!(Some("cat"), find("dog|cat", "a cat and a dog"));
+        assert_eq!(
+            Some("2024-01-31"),
+            find(r"\d{4}-\d{2}-\d{2}", "on 2024-01-31.")
+        );
+        assert_eq!(Some("ab"), find("a{1,2}b", "ab"));
+        assert_eq!(Some("aab"), find("a{2}b", "aaab"));
+        assert_eq!(None, find("^b", "ab"));
+        assert_eq!(Some("b"), find("b$", "ab"));
+        assert_eq!(Some("is"), find(r"\bis\b", "this is it"));
+        assert_eq!(Some("x-y]"), find(r"[\w\-\]]+", "  x-y]  "));
+        assert_eq!(Some("Zz"), find("[^a-y ]+", "abc Zz"));
+        assert_eq!(Some("(a)"), find(r"\(a\)|(?:b)", "(a)"));
+        assert_eq!(Some("{}"), find("{}", "a{}"));
+    }
+
+    #[test]
+    fn stays_linear_on_pathological_patterns() {
+        // exponential for a backtracking engine
+        let text = "a".repeat(30);
+        assert!(!Regex::new("(a*)*b").unwrap().is_match(&text));
+        assert!(Regex::new("(a|aa)*$").unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn escapes_literals() {
+        let text = r"a.b*(c)\d";
+        assert_eq!(Some(text), find(&escape(text), text));
+        assert_eq!(None, find(&escape("a.b"), "axb"));
+    }
+
+    #[test]
+    fn ignores_case_when_asked() {
+        let regex = Regex::case_insensitive("rust[a-c]").unwrap();
+        assert!(regex.is_match("TRUSTB"));
+        assert!(!Regex::new("rust").unwrap().is_match("Rust"));
+    }
+
+    #[test]
+    fn reports_invalid_patterns() {
+        assert_eq!("missing )", Regex::new("(ab").unwrap_err().message);
+        assert_eq!("unmatched )", Regex::new("ab)").unwrap_err().message);
+        assert_eq!("nothing to repeat", Regex::new("*a").unwrap_err().message);
+        assert_eq!("missing ]", Regex::new("[ab").unwrap_err().message);
+        assert_eq!("unknown escape", Regex::new(r"\q").unwrap_err().message);
+        assert!(Regex::new("a{5,2}").is_err());
+        assert!(Regex::new("a{1001}").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+// finds the files to search under the paths on the command line
+// directories are walked depth first in name order, skipping hidden entries and
+// whatever `.gitignore` and `.ignore` files along the way ignore
+
+use crate::glob::Glob;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, DirEntry};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// read in this order in each directory, so `.ignore` has the last word
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
+    }
+}
+
+impl Error for WalkError {}
+
+/// Which files under a directory get searched. Paths named on the command line
+/// are always searched, whatever the filter says.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    // if any, a file must match one of these
+    pub globs: Vec<Glob>,
+    // files and directories matching any of these are skipped
+    pub excludes: Vec<Glob>,
+    pub hidden: bool,
+}
+
+// a pattern matching a path relative to the search root, or just a name
+fn glob_matches(glob: &Glob, relative: &str, name: &str) -> bool {
+    if glob.has_separator() {
+        glob.matches(relative)
+    } else {
+        glob.matches(name)
+    }
+}
+
+impl Filter {
+    fn allows(&self, relative: &str, name: &str, is_dir: bool) -> bool {
+        if !self.hidden && name.starts_with('.') {
+            return false;
+        }
+        // `target/` too, so `target/**` prunes the whole directory
+        let excluded = self.excludes.iter().any(|glob| {
+            glob_matches(glob, relative, name)
+                || (is_dir && glob_matches(glob, &format!("{relative}/"), name))
+        });
+        if excluded {
+            return false;
+        }
+        is_dir
+            || self.globs.is_empty()
+            || self
+                .globs
+                .iter()
+                .any(|glob| glob_matches(glob, relative, name))
+    }
+}
+
+// one line of an ignore file
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    // `!pattern` un-ignores what an earlier rule ignored
+    negated: bool,
+    // `pattern/` only applies to directories
+    dir_only: bool,
+    // matched against the path from the ignore file's directory, not the name
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // a leading `/` anchors the pattern to the ignore file's directory, as a
+        // `/` anywhere else does
+        let anchored = line.starts_with('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        let glob = Glob::new(line);
+
+        Some(Rule {
+            anchored: anchored || glob.has_separator(),
+            glob,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+// the rules of the ignore files in one directory, and those of the directories
+// above it
+#[derive(Debug)]
+struct Ignores {
+    parent: Option<Arc<Ignores>>,
+    // where the directory is, relative to the search root, ending in `/` unless empty
+    base: String,
+    rules: Vec<Rule>,
+}
+
+impl Ignores {
+    fn load(dir: &Path, base: String, parent: Option<Arc<Ignores>>) -> Option<Arc<Ignores>> {
+        let rules: Vec<Rule> = IGNORE_FILES
+            .iter()
+            .filter_map(|name| fs::read_to_string(dir.join(name)).ok())
+            .flat_map(|text| text.lines().filter_map(Rule::parse).collect::<Vec<_>>())
+            .collect();
+
+        if rules.is_empty() {
+            return parent;
+        }
+        Some(Arc::new(Ignores {
+            parent,
+            base,
+            rules,
+        }))
+    }
+
+    // the deepest ignore file with a rule for the path decides, and within a file
+    // the last matching rule
+    fn ignores(&self, relative: &str, name: &str, is_dir: bool) -> bool {
+        let within = relative.strip_prefix(&self.base).unwrap_or(relative);
+        let rule = self.rules.iter().rev().find(|rule| {
+            (is_dir || !rule.dir_only)
+                && rule.glob.matches(if rule.anchored { within } else { name })
+        });
+
+        match (rule, &self.parent) {
+            (Some(rule), _) => !rule.negated,
+            (None, Some(parent)) => parent.ignores(relative, name, is_dir),
+            (None, None) => false,
+        }
+    }
+}
+
+// a directory or file waiting to be looked at
+struct Pending {
+    path: PathBuf,
+    // relative to the search root, `/`-separated
+    relative: String,
+    is_dir: bool,
+    ignores: Option<Arc<Ignores>>,
+}
+
+/// The files to search under some paths, as an iterator. Errors reading a
+/// directory are yielded along the way, and the walk goes on past them.
+pub struct Walk {
+    // popped from the end, so pushed in reverse order
+    pending: Vec<Pending>,
+    // errors reading the last directory, yielded before anything in it
+    errors: Vec<WalkError>,
+    roots: Vec<PathBuf>,
+    filter: Arc<Filter>,
+}
+
+impl Walk {
+    pub fn new(paths: &[PathBuf], filter: Filter) -> Walk {
+        Walk {
+            pending: Vec::new(),
+            errors: Vec::new(),
+            roots: paths.iter().rev().cloned().collect(),
+            filter: Arc::new(filter),
+        }
+    }
+
+    fn read_dir(&mut self, dir: Pending) {
+        let entries = match fs::read_dir(&dir.path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                let path = dir.path;
+                return self.errors.push(WalkError { path, error });
+            }
+        };
+        // an entry that can't be read is reported on its own, and the rest of
+        // the directory is still searched
+        let errors = &mut self.errors;
+        let mut entries: Vec<DirEntry> = entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                // an entry that couldn't be read has nothing but its directory
+                // to name it by
+                Err(error) => {
+                    let path = dir.path.clone();
+                    errors.push(WalkError { path, error });
+                    None
+                }
+            })
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let base = match dir.relative.as_str() {
+            "" => String::new(),
+            relative => format!("{relative}/"),
+        };
+        let ignores = Ignores::load(&dir.path, base.clone(), dir.ignores);
+
+        for entry in entries.into_iter().rev() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = format!("{base}{name}");
+
+            // symlinks to files are searched, but symlinks to directories aren't
+            // followed, which could loop forever
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(error) => {
+                    self.errors.push(WalkError { path, error });
+                    continue;
+                }
+            };
+            let is_dir = file_type.is_dir();
+            if file_type.is_symlink() && !fs::metadata(&path).is_ok_and(|m| m.is_file()) {
+                continue;
+            }
+
+            if !self.filter.allows(&relative, &name, is_dir)
+                || ignores
+                    .as_ref()
+                    .is_some_and(|ignores| ignores.ignores(&relative, &name, is_dir))
+            {
+                continue;
+            }
+
+            self.pending.push(Pending {
+                path,
+                relative,
+                is_dir,
+                ignores: ignores.clone(),
+            });
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, WalkError>;
+
+    fn next(&mut self) -> Option<Result<PathBuf, WalkError>> {
+        loop {
+            if let Some(e) = self.errors.pop() {
+                return Some(Err(e));
+            }
+            let next = match self.pending.pop() {
+                Some(next) => next,
+                None => {
+                    let path = self.roots.pop()?;
+                    // standard input, for the caller to read
+                    if path == Path::new("-") {
+                        return Some(Ok(path));
+                    }
+                    match fs::metadata(&path) {
+                        Ok(metadata) if metadata.is_dir() => Pending {
+                            path,
+                            relative: String::new(),
+                            is_dir: true,
+                            ignores: None,
+                        },
+                        Ok(_) => return Some(Ok(path)),
+                        Err(error) => return Some(Err(WalkError { path, error })),
+                    }
+                }
+            };
+
+            if !next.is_dir {
+                return Some(Ok(next.path));
+            }
+            self.read_dir(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn honours_ignore_files_and_filters() {
+        let root = env::temp_dir().join(format!("io_project_walk_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["src/nested", "target/debug", "logs", ".git"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/nested/mod.rs",
+            "src/nested/notes.txt",
+            "target/debug/out.rs",
+            "logs/a.log",
+            "logs/keep.log",
+            ".git/config",
+            ".hidden.rs",
+            "README.md",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "*.log\n/target\n").unwrap();
+        fs::write(root.join("logs/.ignore"), "!keep.log\n").unwrap();
+
+        let found = |filter: Filter| -> Vec<String> {
+            Walk::new(std::slice::from_ref(&root), filter)
+                .map(|path| {
+                    let path = path.unwrap();
+                    let relative = path.strip_prefix(&root).unwrap();
+                    relative.to_string_lossy().replace('\\', "/")
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            vec![
+                "README.md",
+                "logs/keep.log",
+                "src/main.rs",
+                "src/nested/mod.rs",
+                "src/nested/notes.txt",
+            ],
+            found(Filter::default())
+        );
+        assert_eq!(
+            vec!["src/main.rs"],
+            found(Filter {
+                globs: vec![Glob::new("*.rs")],
+                excludes: vec![Glob::new("src/nested/**")],
+                hidden: false,
+            })
+        );
+        assert!(found(Filter {
+            hidden: true,
+            ..Filter::default()
+        })
+        .contains(&".hidden.rs".to_string()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
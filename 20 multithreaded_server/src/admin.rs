@@ -0,0 +1,101 @@
+//! The admin API, served on a port of its own: health, readiness, and what each
+//! worker of the pool is doing.
+
+use crate::body::BodyConfig;
+use crate::date;
+use crate::hello::PoolMonitor;
+use crate::http::{Request, Response};
+use crate::json::Value;
+use crate::listener::{self, Listener};
+use crate::server::Router;
+use std::io::BufReader;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// an admin client that stalls holds up the others, so it isn't waited on for long
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `/healthz` answers as long as the process does; `/readyz` turns to 503 once
+/// `draining` is set; `/admin/pool` lists the workers.
+pub fn router(monitor: PoolMonitor, draining: Arc<AtomicBool>) -> Router {
+    Router::new()
+        .get("/healthz", |_| {
+            Response::json(200, &Value::object([("status", "ok".into())]))
+        })
+        .get("/readyz", move |_| {
+            let ready = !draining.load(Ordering::SeqCst);
+            let status = if ready { 200 } else { 503 };
+            Response::json(status, &Value::object([("ready", ready.into())]))
+        })
+        .get("/admin/pool", move |_| {
+            Response::json(200, &pool_status(&monitor))
+        })
+}
+
+fn pool_status(monitor: &PoolMonitor) -> Value {
+    let now = SystemTime::now();
+    let workers = monitor.workers();
+    let busy = workers.iter().filter(|w| w.busy_since.is_some()).count();
+
+    let workers = workers
+        .into_iter()
+        .map(|worker| {
+            let state = match worker.busy_since {
+                Some(_) => "busy",
+                None => "idle",
+            };
+            let busy_ms = worker
+                .busy_since
+                .map(|since| now.duration_since(since).unwrap_or_default().as_millis() as u64);
+            Value::object([
+                ("id", worker.id.into()),
+                ("state", state.into()),
+                ("since", worker.busy_since.map(date::iso8601).into()),
+                ("busy_ms", busy_ms.into()),
+                ("request", worker.job.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    Value::object([
+        ("size", workers.len().into()),
+        ("busy", busy.into()),
+        ("queued", monitor.queued().into()),
+        ("workers", workers.into()),
+    ])
+}
+
+/// Answers admin requests on this thread, one at a time, until `stop` is set and
+/// the listener is poked. Nothing here waits for the pool, so the admin API keeps
+/// answering while every worker is busy.
+pub(crate) fn serve(listener: &Listener, router: &Router, stop: &AtomicBool) {
+    loop {
+        let result = listener.accept();
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok((connection, _)) = result else {
+            thread::sleep(listener::ACCEPT_BACKOFF);
+            continue;
+        };
+        if connection.set_read_timeout(Some(READ_TIMEOUT)).is_err() {
+            continue;
+        }
+
+        let mut reader = BufReader::new(connection);
+        let response = match Request::read_from(&mut reader, &BodyConfig::default()) {
+            Ok(request) => router
+                .handle(&request)
+                .unwrap_or_else(|e| e.to_response(&[])),
+            Err(_) => continue,
+        };
+        if let Err(e) = response
+            .with_header("Connection", "close")
+            .write_to(reader.get_mut())
+        {
+            eprintln!("Failed to write admin response: {e}");
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! A load generator for measuring the server, e.g.
+//! `cargo run --release --bin bench -- -c 8 -d 10 -k http://127.0.0.1:7878/`
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Usage: bench [options] <url>
+
+  -c, --connections <n>  concurrent connections (default 4)
+  -d, --duration <secs>  how long to run (default 10)
+  -n, --requests <n>     stop after this many requests instead
+  -k, --keep-alive       reuse connections instead of one per request
+  -r, --rate <n>         target requests per second over all connections
+  -t, --timeout <secs>   give up on a response after this long (default 10)";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    connections: usize,
+    duration: Duration,
+    requests: Option<u64>,
+    keep_alive: bool,
+    rate: Option<f64>,
+    timeout: Duration,
+    host: String,
+    path: String,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            connections: 4,
+            duration: Duration::from_secs(10),
+            requests: None,
+            keep_alive: false,
+            rate: None,
+            timeout: Duration::from_secs(10),
+            host: String::new(),
+            path: String::new(),
+        };
+        let mut url = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+            match arg.as_str() {
+                "-c" | "--connections" => options.connections = number(&value(&arg)?)?,
+                "-d" | "--duration" => options.duration = seconds(&value(&arg)?)?,
+                "-n" | "--requests" => options.requests = Some(number(&value(&arg)?)?),
+                "-k" | "--keep-alive" => options.keep_alive = true,
+                "-r" | "--rate" => options.rate = Some(number(&value(&arg)?)?),
+                "-t" | "--timeout" => options.timeout = seconds(&value(&arg)?)?,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+                _ if url.is_none() => url = Some(arg),
+                _ => return Err("only one url can be given".to_string()),
+            }
+        }
+
+        let url = url.ok_or_else(|| USAGE.to_string())?;
+        let rest = url.strip_prefix("http://").unwrap_or(&url);
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(format!("no host in {url}"));
+        }
+        options.host = host.to_string();
+        options.path = path.to_string();
+
+        if options.connections == 0 {
+            return Err("at least one connection is needed".to_string());
+        }
+        if options
+            .rate
+            .is_some_and(|rate| rate <= 0.0 || !rate.is_finite())
+        {
+            return Err("the rate must be above zero".to_string());
+        }
+        Ok(options)
+    }
+
+    fn request(&self) -> String {
+        let connection = if self.keep_alive {
+            "keep-alive"
+        } else {
+            "close"
+        };
+        format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: {connection}\r\n\r\n",
+            self.path, self.host
+        )
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .ok()
+        .ok_or_else(|| format!("{value} is not a valid number"))
+}
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(number(value)?).map_err(|_| format!("{value} is not a duration"))
+}
+
+/// What one connection's worker saw.
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, u64>,
+    connect_errors: u64,
+    io_errors: u64,
+    timeouts: u64,
+}
+
+impl Stats {
+    fn merge(&mut self, other: Stats) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.connect_errors += other.connect_errors;
+        self.io_errors += other.io_errors;
+        self.timeouts += other.timeouts;
+    }
+
+    fn record_error(&mut self, e: &io::Error) {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => self.timeouts += 1,
+            _ => self.io_errors += 1,
+        }
+    }
+}
+
+/// The value below which `p` percent of the sorted `latencies` fall.
+fn percentile(latencies: &[Duration], p: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+fn main() {
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(2);
+    });
+    let addr = options
+        .host
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .unwrap_or_else(|| {
+            eprintln!("Could not resolve {}", options.host);
+            process::exit(2);
+        });
+
+    match &options.requests {
+        Some(n) => println!(
+            "Sending {n} requests to {addr}{} over {} connections",
+            options.path, options.connections
+        ),
+        None => println!(
+            "Sending requests to {addr}{} over {} connections for {:.1}s",
+            options.path,
+            options.connections,
+            options.duration.as_secs_f64()
+        ),
+    }
+
+    let issued = AtomicU64::new(0);
+    let started = Instant::now();
+    let stats = thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.connections)
+            .map(|_| scope.spawn(|| run_connection(&options, addr, &issued, started)))
+            .collect();
+
+        workers
+            .into_iter()
+            .fold(Stats::default(), |mut total, worker| {
+                total.merge(worker.join().unwrap_or_default());
+                total
+            })
+    });
+
+    report(stats, started.elapsed());
+}
+
+// sends requests one after another until the run is over
+fn run_connection(
+    options: &Options,
+    addr: SocketAddr,
+    issued: &AtomicU64,
+    started: Instant,
+) -> Stats {
+    let mut stats = Stats::default();
+    let request = options.request();
+    let deadline = started + options.duration;
+    // with a target rate each connection takes an equal share, sending on a fixed schedule
+    let interval = options
+        .rate
+        .map(|rate| Duration::from_secs_f64(options.connections as f64 / rate));
+    let mut next_send = started;
+    let mut stream: Option<BufReader<TcpStream>> = None;
+
+    loop {
+        match options.requests {
+            Some(limit) if issued.fetch_add(1, Ordering::SeqCst) >= limit => break,
+            None if Instant::now() >= deadline => break,
+            _ => {}
+        }
+
+        // measured from when the request was due, so a slow server can't hide its
+        // queueing delay by holding back our sends
+        let sent = match interval {
+            Some(interval) => {
+                let due = next_send;
+                next_send += interval;
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+                due
+            }
+            None => Instant::now(),
+        };
+
+        let reader = match stream.take() {
+            Some(reader) => reader,
+            None => match connect(addr, options.timeout) {
+                Ok(reader) => reader,
+                Err(_) => {
+                    stats.connect_errors += 1;
+                    continue;
+                }
+            },
+        };
+
+        match send(reader, &request) {
+            Ok((status, reader)) => {
+                stats.latencies.push(sent.elapsed());
+                *stats.statuses.entry(status).or_default() += 1;
+                if options.keep_alive {
+                    stream = reader;
+                }
+            }
+            Err(e) => stats.record_error(&e),
+        }
+    }
+
+    stats
+}
+
+fn connect(addr: SocketAddr, timeout: Duration) -> io::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(BufReader::new(stream))
+}
+
+// writes the request and reads the whole response, handing the connection back if
+// the server is keeping it open
+fn send(
+    mut reader: BufReader<TcpStream>,
+    request: &str,
+) -> io::Result<(u16, Option<BufReader<TcpStream>>)> {
+    reader.get_mut().write_all(request.as_bytes())?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad status line"))?;
+
+    let mut length = None;
+    let mut close = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.parse::<u64>().ok();
+            } else if name.eq_ignore_ascii_case("Connection") {
+                close = value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+
+    // without a length the body runs until the server closes the connection
+    match length {
+        Some(length) => {
+            io::copy(&mut (&mut reader).take(length), &mut io::sink())?;
+        }
+        None => {
+            io::copy(&mut reader, &mut io::sink())?;
+            close = true;
+        }
+    }
+
+    Ok((status, (!close).then_some(reader)))
+}
+
+fn report(mut stats: Stats, elapsed: Duration) {
+    stats.latencies.sort();
+    let completed = stats.latencies.len();
+    let ms = |d: Duration| format!("{:.2}ms", d.as_secs_f64() * 1000.0);
+
+    println!(
+        "Requests:  {completed} in {:.2}s, {:.1} req/s",
+        elapsed.as_secs_f64(),
+        completed as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "Latency:   p50 {}  p90 {}  p99 {}  max {}",
+        ms(percentile(&stats.latencies, 50.0)),
+        ms(percentile(&stats.latencies, 90.0)),
+        ms(percentile(&stats.latencies, 99.0)),
+        ms(stats.latencies.last().copied().unwrap_or_default()),
+    );
+
+    let statuses: Vec<_> = stats
+        .statuses
+        .iter()
+        .map(|(status, count)| format!("{status}: {count}"))
+        .collect();
+    println!("Statuses:  {}", statuses.join(", "));
+
+    let failed_statuses: u64 = stats.statuses.range(400..).map(|(_, count)| count).sum();
+    println!(
+        "Errors:    {} connect, {} read/write, {} timeout, {} 4xx/5xx",
+        stats.connect_errors, stats.io_errors, stats.timeouts, failed_statuses
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn parses_options() {
+        let options =
+            Options::parse(args("-c 8 -n 100 -k --rate 50 http://localhost:7878/sleep")).unwrap();
+
+        assert_eq!(8, options.connections);
+        assert_eq!(Some(100), options.requests);
+        assert!(options.keep_alive);
+        assert_eq!(Some(50.0), options.rate);
+        assert_eq!("localhost:7878", options.host);
+        assert_eq!("/sleep", options.path);
+
+        assert!(Options::parse(args("-c 0 localhost:7878")).is_err());
+        assert!(Options::parse(args("-x localhost:7878")).is_err());
+    }
+
+    #[test]
+    fn picks_percentiles() {
+        let latencies: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+
+        assert_eq!(Duration::from_millis(50), percentile(&latencies, 50.0));
+        assert_eq!(Duration::from_millis(99), percentile(&latencies, 99.0));
+        assert_eq!(Duration::from_millis(100), percentile(&latencies, 100.0));
+    }
+}
@@ -0,0 +1,587 @@
+//! Extraction of request bodies: url-encoded forms, JSON and multipart uploads.
+
+use crate::http::{self, Headers};
+use crate::json;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// how much of a multipart body is buffered at once while looking for the boundary
+const CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct BodyConfig {
+    /// Requests whose `Content-Length` exceeds this are rejected with 413.
+    pub max_size: u64,
+    /// Where file parts of multipart uploads are written.
+    pub upload_dir: PathBuf,
+}
+
+impl Default for BodyConfig {
+    fn default() -> BodyConfig {
+        BodyConfig {
+            max_size: 1024 * 1024,
+            upload_dir: env::temp_dir().join("multithreaded_server_uploads"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Body {
+    Empty,
+    Form(Vec<(String, String)>),
+    Json(json::Value),
+    Multipart(Multipart),
+    /// Any other content type, left undecoded.
+    Raw(Vec<u8>),
+}
+
+impl Body {
+    /// Looks up a field of a form or multipart body.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        let fields = match self {
+            Body::Form(fields) => fields,
+            Body::Multipart(multipart) => &multipart.fields,
+            _ => return None,
+        };
+
+        fields
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Describes the body as JSON, e.g. for echoing it back to the client.
+    pub fn to_json(&self) -> json::Value {
+        let fields = |fields: &[(String, String)]| {
+            json::Value::object(fields.iter().map(|(k, v)| (k.as_str(), v.as_str().into())))
+        };
+
+        match self {
+            Body::Empty => json::Value::object([("kind", "empty".into())]),
+            Body::Form(form) => {
+                json::Value::object([("kind", "form".into()), ("fields", fields(form))])
+            }
+            Body::Json(value) => {
+                json::Value::object([("kind", "json".into()), ("value", value.clone())])
+            }
+            Body::Multipart(multipart) => json::Value::object([
+                ("kind", "multipart".into()),
+                ("fields", fields(&multipart.fields)),
+                (
+                    "files",
+                    json::Value::Array(multipart.files.iter().map(UploadedFile::to_json).collect()),
+                ),
+            ]),
+            Body::Raw(bytes) => {
+                json::Value::object([("kind", "raw".into()), ("length", bytes.len().into())])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Multipart {
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<UploadedFile>,
+}
+
+/// A file part that was streamed to disk. The file is deleted when this is
+/// dropped, along with the request, so a handler that wants to keep it must move
+/// or copy it elsewhere first.
+#[derive(Debug)]
+pub struct UploadedFile {
+    pub field: String,
+    /// The client-supplied name with any directory components removed.
+    pub filename: String,
+    pub content_type: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        // already gone if the handler moved it
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl UploadedFile {
+    fn to_json(&self) -> json::Value {
+        json::Value::object([
+            ("field", self.field.as_str().into()),
+            ("filename", self.filename.as_str().into()),
+            ("content_type", self.content_type.as_str().into()),
+            ("size", self.size.into()),
+        ])
+    }
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    Io(io::Error),
+    TooLarge,
+    LengthRequired,
+    UnsupportedEncoding,
+    Malformed(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            BodyError::Io(_) => None,
+            BodyError::TooLarge => Some(413),
+            BodyError::LengthRequired => Some(411),
+            BodyError::UnsupportedEncoding => Some(501),
+            BodyError::Malformed(_) => Some(400),
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BodyError::Io(e) => write!(f, "I/O error reading body: {e}"),
+            BodyError::TooLarge => write!(f, "request body too large"),
+            BodyError::LengthRequired => write!(f, "Content-Length required"),
+            BodyError::UnsupportedEncoding => write!(f, "unsupported Transfer-Encoding"),
+            BodyError::Malformed(reason) => write!(f, "malformed body: {reason}"),
+        }
+    }
+}
+
+impl Error for BodyError {}
+
+impl From<io::Error> for BodyError {
+    fn from(e: io::Error) -> BodyError {
+        BodyError::Io(e)
+    }
+}
+
+/// Reads exactly `Content-Length` bytes from `reader` and decodes them.
+pub fn read_body<R: BufRead>(
+    method: &str,
+    headers: &Headers,
+    reader: &mut R,
+    config: &BodyConfig,
+) -> Result<Body, BodyError> {
+    if headers.contains("Transfer-Encoding") {
+        return Err(BodyError::UnsupportedEncoding);
+    }
+
+    let length: u64 = match content_length(headers)? {
+        Some(length) => length,
+        // only methods that carry a body need to say how long it is
+        None if matches!(method, "POST" | "PUT" | "PATCH") && headers.contains("Content-Type") => {
+            return Err(BodyError::LengthRequired)
+        }
+        None => return Ok(Body::Empty),
+    };
+
+    // checked up front so an oversized upload is never read
+    if length > config.max_size {
+        return Err(BodyError::TooLarge);
+    }
+    if length == 0 {
+        return Ok(Body::Empty);
+    }
+
+    let content_type = headers.get("Content-Type").unwrap_or("");
+    let (media_type, params) = split_media_type(content_type);
+    let mut limited = reader.take(length);
+
+    let body = match media_type.as_str() {
+        "multipart/form-data" => {
+            let boundary = param(params, "boundary").ok_or_else(|| {
+                BodyError::Malformed("multipart body without boundary".to_string())
+            })?;
+            Body::Multipart(read_multipart(&mut limited, &boundary, &config.upload_dir)?)
+        }
+        "application/x-www-form-urlencoded" => {
+            let bytes = read_exact_body(&mut limited, length)?;
+            let text = String::from_utf8(bytes)
+                .map_err(|_| BodyError::Malformed("form is not UTF-8".to_string()))?;
+            Body::Form(http::parse_urlencoded(&text))
+        }
+        "application/json" => {
+            let bytes = read_exact_body(&mut limited, length)?;
+            let text = String::from_utf8(bytes)
+                .map_err(|_| BodyError::Malformed("JSON is not UTF-8".to_string()))?;
+            Body::Json(json::parse(&text).map_err(|e| BodyError::Malformed(e.to_string()))?)
+        }
+        _ => Body::Raw(read_exact_body(&mut limited, length)?),
+    };
+
+    // drain anything a multipart epilogue left behind so the stream stays in sync
+    io::copy(&mut limited, &mut io::sink())?;
+
+    Ok(body)
+}
+
+fn read_exact_body<R: Read>(reader: &mut R, length: u64) -> Result<Vec<u8>, BodyError> {
+    let mut bytes = Vec::with_capacity(length as usize);
+    reader.read_to_end(&mut bytes)?;
+
+    if (bytes.len() as u64) < length {
+        return Err(BodyError::Malformed(
+            "body shorter than Content-Length".to_string(),
+        ));
+    }
+
+    Ok(bytes)
+}
+
+// "text/html; charset=utf-8" -> ("text/html", " charset=utf-8")
+fn split_media_type(value: &str) -> (String, &str) {
+    let (media_type, params) = value.split_once(';').unwrap_or((value, ""));
+    (media_type.trim().to_ascii_lowercase(), params)
+}
+
+// finds `name=value` or `name="value"` in a `;`-separated parameter list
+fn param(params: &str, name: &str) -> Option<String> {
+    params.split(';').find_map(|p| {
+        let (key, value) = p.trim().split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+fn read_multipart<R: Read>(
+    reader: &mut R,
+    boundary: &str,
+    upload_dir: &Path,
+) -> Result<Multipart, BodyError> {
+    let mut stream = PartStream::new(reader, boundary);
+    let mut multipart = Multipart::default();
+
+    // the preamble before the first boundary is discarded
+    if !stream.skip_to_boundary()? {
+        return Err(BodyError::Malformed("missing first boundary".to_string()));
+    }
+
+    loop {
+        if stream.at_final_boundary()? {
+            return Ok(multipart);
+        }
+
+        let headers = stream.part_headers()?;
+        let disposition = headers.get("Content-Disposition").unwrap_or("");
+        let name = param(disposition, "name")
+            .ok_or_else(|| BodyError::Malformed("part without a name".to_string()))?;
+
+        match param(disposition, "filename") {
+            Some(filename) => {
+                let filename = sanitize_filename(&filename);
+                let content_type = headers
+                    .get("Content-Type")
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+
+                fs::create_dir_all(upload_dir)?;
+                // made before the file is, so a failure from here on, in this
+                // part or a later one, deletes it again
+                let mut upload = UploadedFile {
+                    path: upload_dir.join(unique_name(&filename)),
+                    field: name,
+                    filename,
+                    content_type,
+                    size: 0,
+                };
+                let mut file = File::create(&upload.path)?;
+                upload.size = stream.copy_part(&mut file)?;
+                multipart.files.push(upload);
+            }
+            None => {
+                let mut value = Vec::new();
+                stream.copy_part(&mut value)?;
+                let value = String::from_utf8(value)
+                    .map_err(|_| BodyError::Malformed("form field is not UTF-8".to_string()))?;
+                multipart.fields.push((name, value));
+            }
+        }
+    }
+}
+
+// keeps only the last path component so a filename can't escape the upload directory
+fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+
+    match name.as_str() {
+        "" | "." | ".." => "upload".to_string(),
+        _ => name,
+    }
+}
+
+fn unique_name(filename: &str) -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("{}-{nanos}-{n}-{filename}", std::process::id())
+}
+
+/// Splits a multipart body into parts without holding more than a chunk in memory.
+struct PartStream<'a, R> {
+    reader: &'a mut R,
+    // "\r\n--boundary": what ends every part's content
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<'a, R: Read> PartStream<'a, R> {
+    fn new(reader: &'a mut R, boundary: &str) -> PartStream<'a, R> {
+        // the leading CRLF lets the first boundary be matched like the others
+        PartStream {
+            reader,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buf: b"\r\n".to_vec(),
+            eof: false,
+        }
+    }
+
+    // tops up the buffer; returns false once the body is exhausted
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let mut chunk = [0; CHUNK_SIZE];
+        let n = self.reader.read(&mut chunk)?;
+        if n == 0 {
+            self.eof = true;
+            return Ok(false);
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(true)
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buf.windows(needle.len()).position(|w| w == needle)
+    }
+
+    fn skip_to_boundary(&mut self) -> Result<bool, BodyError> {
+        let mut sink = io::sink();
+        match self.copy_part(&mut sink) {
+            Ok(_) => Ok(true),
+            Err(BodyError::Malformed(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // called right after a boundary: "--" marks the end, CRLF starts another part
+    fn at_final_boundary(&mut self) -> Result<bool, BodyError> {
+        while self.buf.len() < 2 && self.fill()? {}
+
+        if self.buf.starts_with(b"--") {
+            self.buf.clear();
+            return Ok(true);
+        }
+        if self.buf.starts_with(b"\r\n") {
+            self.buf.drain(..2);
+            return Ok(false);
+        }
+        Err(BodyError::Malformed("truncated multipart body".to_string()))
+    }
+
+    fn part_headers(&mut self) -> Result<Headers, BodyError> {
+        let end = loop {
+            if let Some(pos) = self.find(b"\r\n\r\n") {
+                break pos;
+            }
+            // a part with no headers at all starts directly with the blank line
+            if self.buf.starts_with(b"\r\n") {
+                self.buf.drain(..2);
+                return Ok(Headers::new());
+            }
+            if self.buf.len() > CHUNK_SIZE * 2 {
+                return Err(BodyError::Malformed("part headers too large".to_string()));
+            }
+            if !self.fill()? {
+                return Err(BodyError::Malformed("truncated part headers".to_string()));
+            }
+        };
+
+        let head: Vec<u8> = self.buf.drain(..end + 4).collect();
+        let head = String::from_utf8(head)
+            .map_err(|_| BodyError::Malformed("part headers are not UTF-8".to_string()))?;
+
+        let mut headers = Headers::new();
+        for line in head.split("\r\n").filter(|l| !l.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| BodyError::Malformed("invalid part header".to_string()))?;
+            headers.append(name.trim(), value.trim());
+        }
+        Ok(headers)
+    }
+
+    // writes the part's content to `out` and consumes the delimiter that follows it
+    fn copy_part<W: Write>(&mut self, out: &mut W) -> Result<u64, BodyError> {
+        let mut written = 0;
+
+        loop {
+            if let Some(pos) = self.find(&self.delimiter) {
+                out.write_all(&self.buf[..pos])?;
+                self.buf.drain(..pos + self.delimiter.len());
+                return Ok(written + pos as u64);
+            }
+
+            // everything except a possible partial delimiter at the end is content
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let flush = self.buf.len() - keep;
+                out.write_all(&self.buf[..flush])?;
+                self.buf.drain(..flush);
+                written += flush as u64;
+            }
+
+            if !self.fill()? {
+                return Err(BodyError::Malformed("missing closing boundary".to_string()));
+            }
+        }
+    }
+}
+
+// the length from every `Content-Length`, which must all agree: a server that
+// took one and a proxy that took another would disagree on where the next
+// request starts
+fn content_length(headers: &Headers) -> Result<Option<u64>, BodyError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value
+            .trim()
+            .parse()
+            .map_err(|_| BodyError::Malformed("invalid Content-Length".to_string()))?;
+        if length.is_some_and(|length| length != value) {
+            return Err(BodyError::Malformed(
+                "conflicting Content-Length".to_string(),
+            ));
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::temp_dir;
+
+    fn headers(content_type: &str, length: usize) -> Headers {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", content_type);
+        headers.append("Content-Length", length.to_string());
+        headers
+    }
+
+    #[test]
+    fn decodes_urlencoded_form() {
+        let data = b"name=Ferris+the+crab&lang=rust%21";
+        let body = read_body(
+            "POST",
+            &headers("application/x-www-form-urlencoded", data.len()),
+            &mut &data[..],
+            &BodyConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(Some("Ferris the crab"), body.field("name"));
+        assert_eq!(Some("rust!"), body.field("lang"));
+    }
+
+    #[test]
+    fn streams_multipart_files_to_disk() {
+        let data = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"doc\"; filename=\"../../notes.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line one\r\nline two\r\n--XyZ--\r\n";
+        let upload_dir = temp_dir("body_uploads");
+        let config = BodyConfig {
+            upload_dir: upload_dir.to_path_buf(),
+            ..BodyConfig::default()
+        };
+
+        let body = read_body(
+            "POST",
+            &headers("multipart/form-data; boundary=XyZ", data.len()),
+            &mut data.as_bytes(),
+            &config,
+        )
+        .unwrap();
+
+        let Body::Multipart(multipart) = body else {
+            panic!("expected a multipart body");
+        };
+        assert_eq!(
+            vec![("title".to_string(), "hello".to_string())],
+            multipart.fields
+        );
+
+        let file = &multipart.files[0];
+        assert_eq!("notes.txt", file.filename);
+        assert_eq!(
+            "line one\r\nline two",
+            fs::read_to_string(&file.path).unwrap()
+        );
+
+        let path = file.path.clone();
+        drop(multipart);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn removes_uploads_when_a_later_part_fails() {
+        let data = "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"doc\"; filename=\"a.txt\"\r\n\r\n\
+            contents\r\n--XyZ\r\n\
+            Content-Disposition: form-data\r\n\r\n\
+            nameless\r\n--XyZ--\r\n";
+        let upload_dir = temp_dir("body_failure");
+        let config = BodyConfig {
+            upload_dir: upload_dir.to_path_buf(),
+            ..BodyConfig::default()
+        };
+
+        let err = read_body(
+            "POST",
+            &headers("multipart/form-data; boundary=XyZ", data.len()),
+            &mut data.as_bytes(),
+            &config,
+        )
+        .unwrap_err();
+
+        assert_eq!(Some(400), err.status());
+        assert_eq!(0, fs::read_dir(&upload_dir).unwrap().count());
+    }
+
+    #[test]
+    fn rejects_oversized_body_without_reading_it() {
+        let config = BodyConfig {
+            max_size: 4,
+            ..BodyConfig::default()
+        };
+
+        let err = read_body(
+            "POST",
+            &headers("application/json", 5),
+            &mut &b"[1,2]"[..],
+            &config,
+        )
+        .unwrap_err();
+
+        assert_eq!(Some(413), err.status());
+    }
+}
@@ -0,0 +1,551 @@
+//! An HTTP/1.1 client for calling other services, and the server itself in tests.
+//!
+//! Responses come back as the same [`Response`] the server sends, with the body
+//! read in full and chunked bodies already decoded. Only plain `http://` URLs
+//! are supported.
+
+use crate::http::{self, Headers, ParseError, Response};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// idle connections kept per host; more than this are closed after use
+const MAX_IDLE_PER_HOST: usize = 8;
+
+#[derive(Debug)]
+pub enum ClientError {
+    InvalidUrl(String),
+    /// Connecting, sending or receiving failed.
+    Io(io::Error),
+    /// The server took longer than the client's timeout to connect or answer.
+    Timeout,
+    /// The server's answer isn't valid HTTP.
+    Parse(ParseError),
+    /// The body is bigger than the client accepts.
+    TooLarge,
+    /// The redirect limit was reached; holds the limit.
+    TooManyRedirects(usize),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "invalid URL {url}"),
+            ClientError::Io(e) => write!(f, "I/O error: {e}"),
+            ClientError::Timeout => write!(f, "timed out"),
+            ClientError::Parse(e) => write!(f, "invalid response: {e}"),
+            ClientError::TooLarge => write!(f, "response body too large"),
+            ClientError::TooManyRedirects(limit) => write!(f, "more than {limit} redirects"),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+            _ => ClientError::Io(e),
+        }
+    }
+}
+
+impl From<ParseError> for ClientError {
+    fn from(e: ParseError) -> ClientError {
+        match e {
+            ParseError::Io(e) => e.into(),
+            e => ClientError::Parse(e),
+        }
+    }
+}
+
+/// A request to send with [`Client::send`].
+#[derive(Debug, Clone)]
+pub struct ClientRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl ClientRequest {
+    pub fn new(method: &str, url: &str) -> ClientRequest {
+        ClientRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> ClientRequest {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> ClientRequest {
+        self.body = body.into();
+        self
+    }
+}
+
+// the parts of an `http://` URL the client needs
+#[derive(Debug, PartialEq)]
+struct Url {
+    host: String,
+    port: u16,
+    // the path and query, sent as the request target
+    target: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, ClientError> {
+        let invalid = || ClientError::InvalidUrl(url.to_string());
+
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, target) = match rest.find(['/', '?', '#']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        // a fragment is for the client alone
+        let target = target.split_once('#').map_or(target, |(target, _)| target);
+        let target = match target {
+            "" => "/".to_string(),
+            query if query.starts_with('?') => format!("/{query}"),
+            path => path.to_string(),
+        };
+
+        // the port follows the last colon, unless that is inside an IPv6 literal
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, 80),
+        };
+        if host.is_empty() || host.contains(['@', ' ']) {
+            return Err(invalid());
+        }
+
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    // the value for the Host header
+    fn authority(&self) -> String {
+        match self.port {
+            80 => self.host.clone(),
+            port => format!("{}:{port}", self.host),
+        }
+    }
+
+    /// Resolves a `Location` header against this URL.
+    fn join(&self, location: &str) -> Result<Url, ClientError> {
+        if has_scheme(location) {
+            return Url::parse(location);
+        }
+        let origin = format!("http://{}", self.authority());
+        if let Some(rest) = location.strip_prefix("//") {
+            return Url::parse(&format!("http://{rest}"));
+        }
+        if location.starts_with('/') {
+            return Url::parse(&format!("{origin}{location}"));
+        }
+
+        let path = self.target.split('?').next().unwrap_or("/");
+        if location.starts_with('?') {
+            return Url::parse(&format!("{origin}{path}{location}"));
+        }
+
+        // relative to the directory of the current path
+        let dir = &path[..=path.rfind('/').unwrap_or(0)];
+        Url::parse(&format!("{origin}{dir}{location}"))
+    }
+}
+
+// whether `location` starts with a scheme, like `http:`, which makes it absolute
+fn has_scheme(location: &str) -> bool {
+    location.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+type Connection = BufReader<TcpStream>;
+// idle connections by host and port, each with when it was last used
+type IdleConnections = HashMap<(String, u16), Vec<(Connection, Instant)>>;
+
+/// Sends requests, keeping connections open for reuse between requests to the
+/// same host. Share one client between threads rather than making one per request.
+pub struct Client {
+    idle: Mutex<IdleConnections>,
+    timeout: Duration,
+    idle_timeout: Duration,
+    max_redirects: usize,
+    max_body_size: u64,
+}
+
+impl Client {
+    /// A client with a 30 second timeout that follows up to 10 redirects.
+    pub fn new() -> Client {
+        Client {
+            idle: Mutex::new(HashMap::new()),
+            timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(4),
+            max_redirects: 10,
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// How long to wait for a connection, and for each read and write on it.
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long an unused connection is kept. Keep this below the server's
+    /// keep-alive, or requests will find the server has closed it.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Client {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Follows at most `limit` redirects, failing with
+    /// [`ClientError::TooManyRedirects`] on the next; 0 returns redirects as they are.
+    pub fn with_max_redirects(mut self, limit: usize) -> Client {
+        self.max_redirects = limit;
+        self
+    }
+
+    pub fn with_max_body_size(mut self, max_size: u64) -> Client {
+        self.max_body_size = max_size;
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, ClientError> {
+        self.send(ClientRequest::new("GET", url))
+    }
+
+    pub fn post(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> Result<Response, ClientError> {
+        self.send(
+            ClientRequest::new("POST", url)
+                .with_header("Content-Type", content_type)
+                .with_body(body),
+        )
+    }
+
+    /// Sends `request`, following redirects.
+    ///
+    /// 301, 302 and 303 redirects turn a request other than `HEAD` into a `GET`
+    /// without a body, as browsers do; 307 and 308 repeat it as it was.
+    /// Credentials aren't sent on to another host.
+    pub fn send(&self, mut request: ClientRequest) -> Result<Response, ClientError> {
+        let mut url = Url::parse(&request.url)?;
+
+        for redirects in 0.. {
+            let response = self.send_once(&request, &url)?;
+
+            let location = match response.status {
+                301 | 302 | 303 | 307 | 308 if self.max_redirects > 0 => {
+                    response.headers.get("Location")
+                }
+                _ => None,
+            };
+            let Some(location) = location else {
+                return Ok(response);
+            };
+            if redirects == self.max_redirects {
+                return Err(ClientError::TooManyRedirects(self.max_redirects));
+            }
+
+            let next = url.join(location)?;
+            if (next.host.as_str(), next.port) != (url.host.as_str(), url.port) {
+                request.headers.remove("Authorization");
+                request.headers.remove("Cookie");
+            }
+            if matches!(response.status, 301..=303) && request.method != "HEAD" {
+                request.method = "GET".to_string();
+                request.body.clear();
+                request.headers.remove("Content-Type");
+            }
+            url = next;
+        }
+        unreachable!("the redirect loop only ends by returning")
+    }
+
+    fn send_once(&self, request: &ClientRequest, url: &Url) -> Result<Response, ClientError> {
+        if let Some(connection) = self.take_idle(url) {
+            match self.exchange(connection, request, url) {
+                // the server may have closed the connection while it sat idle; that
+                // is only safe to retry when repeating the request does no harm
+                Err(ClientError::Io(_)) | Err(ClientError::Parse(ParseError::ConnectionClosed))
+                    if is_idempotent(&request.method) => {}
+                result => return result,
+            }
+        }
+
+        let connection = self.connect(url)?;
+        self.exchange(connection, request, url)
+    }
+
+    fn connect(&self, url: &Url) -> Result<Connection, ClientError> {
+        let addrs = (url.host.trim_matches(['[', ']']), url.port).to_socket_addrs()?;
+
+        let mut last_error = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(BufReader::new(stream));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host not found"))
+            .into())
+    }
+
+    fn take_idle(&self, url: &Url) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let connections = idle.get_mut(&(url.host.clone(), url.port))?;
+
+        // the most recently used is the least likely to have been closed
+        while let Some((connection, since)) = connections.pop() {
+            if since.elapsed() < self.idle_timeout {
+                return Some(connection);
+            }
+        }
+        None
+    }
+
+    fn put_idle(&self, url: &Url, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        let connections = idle.entry((url.host.clone(), url.port)).or_default();
+
+        connections.retain(|(_, since)| since.elapsed() < self.idle_timeout);
+        if connections.len() < MAX_IDLE_PER_HOST {
+            connections.push((connection, Instant::now()));
+        }
+    }
+
+    // writes the request and reads the response, pooling the connection afterwards
+    // if the server keeps it open
+    fn exchange(
+        &self,
+        mut connection: Connection,
+        request: &ClientRequest,
+        url: &Url,
+    ) -> Result<Response, ClientError> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            request.method,
+            url.target,
+            url.authority()
+        );
+        for (name, value) in request.headers.iter() {
+            if !["Host", "Content-Length", "Transfer-Encoding"]
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(name))
+            {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        if !request.body.is_empty() || matches!(request.method.as_str(), "POST" | "PUT") {
+            head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+        }
+        head.push_str("\r\n");
+
+        let stream = connection.get_mut();
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&request.body)?;
+        stream.flush()?;
+
+        let (response, reusable) = self.read_response(&mut connection, &request.method)?;
+        if reusable {
+            self.put_idle(url, connection);
+        }
+        Ok(response)
+    }
+
+    // reads one response, and whether the connection can be used for another
+    fn read_response(
+        &self,
+        reader: &mut Connection,
+        method: &str,
+    ) -> Result<(Response, bool), ClientError> {
+        // informational responses such as 100 Continue come before the real one
+        let (version, status, headers) = loop {
+            let (status_line, headers) = http::read_head(reader)?;
+            let mut parts = status_line.splitn(3, ' ');
+            let (version, status) = match (parts.next(), parts.next()) {
+                (Some(version), Some(status)) if version.starts_with("HTTP/1.") => {
+                    match status.parse::<u16>() {
+                        Ok(status) if (100..600).contains(&status) => (version, status),
+                        _ => return Err(ParseError::Malformed("invalid status line").into()),
+                    }
+                }
+                _ => return Err(ParseError::Malformed("invalid status line").into()),
+            };
+            if !(100..200).contains(&status) {
+                break (version.to_string(), status, headers);
+            }
+        };
+
+        let has = |name: &str, token: &str| {
+            headers
+                .get_all(name)
+                .flat_map(|value| value.split(','))
+                .any(|t| t.trim().eq_ignore_ascii_case(token))
+        };
+        let mut reusable = match version.as_str() {
+            "HTTP/1.1" => !has("Connection", "close"),
+            _ => has("Connection", "keep-alive"),
+        };
+
+        let body = if method == "HEAD" || status == 204 || status == 304 {
+            Vec::new()
+        } else if has("Transfer-Encoding", "chunked") {
+            self.read_chunked(reader)?
+        } else if let Some(length) = headers.get("Content-Length") {
+            let length: u64 = length
+                .parse()
+                .map_err(|_| ParseError::Malformed("invalid Content-Length"))?;
+            if length > self.max_body_size {
+                return Err(ClientError::TooLarge);
+            }
+            let mut body = Vec::new();
+            reader.take(length).read_to_end(&mut body)?;
+            if (body.len() as u64) < length {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            body
+        } else {
+            // the body runs until the server closes the connection
+            reusable = false;
+            self.read_limited(reader)?
+        };
+
+        let mut response = Response::new(status).with_body(body);
+        response.headers = headers;
+        Ok((response, reusable))
+    }
+
+    fn read_chunked(&self, reader: &mut Connection) -> Result<Vec<u8>, ClientError> {
+        let mut body = Vec::new();
+        loop {
+            let line = http::read_line(reader)?
+                .ok_or(ParseError::Malformed("unexpected end of chunked body"))?;
+            // extensions after `;` carry nothing we use
+            let size = line.split(';').next().unwrap_or("").trim();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| ParseError::Malformed("invalid chunk size"))?;
+
+            if size == 0 {
+                // trailer fields, if any, up to an empty line
+                while !http::read_line(reader)?
+                    .ok_or(ParseError::Malformed("unexpected end of chunked body"))?
+                    .is_empty()
+                {}
+                return Ok(body);
+            }
+            // subtracted rather than added, which a huge size could overflow
+            if size > self.max_body_size.saturating_sub(body.len() as u64) {
+                return Err(ClientError::TooLarge);
+            }
+
+            let start = body.len();
+            reader.take(size).read_to_end(&mut body)?;
+            if ((body.len() - start) as u64) < size {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            if http::read_line(reader)? != Some(String::new()) {
+                return Err(ParseError::Malformed("chunk not followed by CRLF").into());
+            }
+        }
+    }
+
+    fn read_limited(&self, reader: &mut Connection) -> Result<Vec<u8>, ClientError> {
+        let mut body = Vec::new();
+        reader.take(self.max_body_size + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > self.max_body_size {
+            return Err(ClientError::TooLarge);
+        }
+        Ok(body)
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+fn is_idempotent(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("http://localhost:7878/a/b?c=d#top").unwrap();
+        assert_eq!("localhost", url.host);
+        assert_eq!(7878, url.port);
+        assert_eq!("/a/b?c=d", url.target);
+
+        let url = Url::parse("http://[::1]?q").unwrap();
+        assert_eq!(
+            ("[::1]", 80, "/?q"),
+            (url.host.as_str(), url.port, url.target.as_str())
+        );
+
+        let url = Url::parse("http://example.com#top").unwrap();
+        assert_eq!(
+            ("example.com", 80, "/"),
+            (url.host.as_str(), url.port, url.target.as_str())
+        );
+        assert_eq!(
+            "/?q",
+            Url::parse("http://example.com:81?q#top").unwrap().target
+        );
+
+        assert!(Url::parse("https://example.com/").is_err());
+        assert!(Url::parse("http://:80/").is_err());
+        assert!(Url::parse("http://example.com:http/").is_err());
+    }
+
+    #[test]
+    fn resolves_redirect_locations() {
+        let url = Url::parse("http://example.com:8080/docs/guide?page=2").unwrap();
+        let target = |location| url.join(location).unwrap().target;
+
+        assert_eq!("/login", target("/login"));
+        assert_eq!("/docs/intro", target("intro"));
+        assert_eq!("/docs/guide?page=3", target("?page=3"));
+        assert_eq!("/login?next=http://x/", target("/login?next=http://x/"));
+        assert_eq!(
+            Url::parse("http://other.test/x").unwrap(),
+            url.join("http://other.test/x").unwrap()
+        );
+        assert_eq!("other.test", url.join("//other.test/").unwrap().host);
+    }
+}
@@ -0,0 +1,181 @@
+//! Reading the `Cookie` header and building `Set-Cookie` values.
+
+use crate::http::{Request, Response};
+use std::fmt;
+use std::time::Duration;
+
+/// Splits a `Cookie` header value into name/value pairs, in order.
+///
+/// Pairs without a `=` are skipped and double-quoted values are unquoted.
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+impl Request {
+    /// The first cookie named `name`, looking through every `Cookie` header.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(parse)
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl Response {
+    /// Adds a `Set-Cookie` header; several cookies can be set on one response.
+    pub fn with_cookie(mut self, cookie: &Cookie) -> Response {
+        self.headers.append("Set-Cookie", cookie.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to send to the client; its `Display` is the `Set-Cookie` value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<Duration>,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A session cookie, kept by the browser until it closes. `name` and `value`
+    /// are sent as given, so they must not contain `;`, `,`, quotes or whitespace.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// A cookie that makes the browser delete the cookie `name` set with the same path.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "").with_max_age(Duration::ZERO)
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Keeps the cookie for `max_age`, rounded down to whole seconds.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Hides the cookie from scripts in the page.
+    pub fn with_http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Only sends the cookie back over HTTPS.
+    pub fn with_secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cookie_header() {
+        assert_eq!(
+            vec![
+                ("session".to_string(), "abc123".to_string()),
+                ("theme".to_string(), "dark mode".to_string()),
+            ],
+            parse(r#"session=abc123; bare; theme="dark mode";=nameless"#)
+        );
+    }
+
+    #[test]
+    fn formats_set_cookie() {
+        let cookie = Cookie::new("session", "abc123")
+            .with_path("/")
+            .with_domain("example.com")
+            .with_max_age(Duration::from_secs(3600))
+            .with_http_only(true)
+            .with_secure(true)
+            .with_same_site(SameSite::Lax);
+
+        assert_eq!(
+            "session=abc123; Path=/; Domain=example.com; Max-Age=3600; HttpOnly; Secure; SameSite=Lax",
+            cookie.to_string()
+        );
+        assert_eq!("old=; Max-Age=0", Cookie::removal("old").to_string());
+    }
+}
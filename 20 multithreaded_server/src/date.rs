@@ -0,0 +1,107 @@
+//! Formatting of timestamps for headers and logs, always in UTC.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+struct Parts {
+    year: i64,
+    month: usize,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+    weekday: usize,
+}
+
+fn parts(time: SystemTime) -> Parts {
+    // times before 1970 don't occur in practice and are clamped to the epoch
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let days = secs / 86_400;
+    let rem = secs % 86_400;
+
+    // converts days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u64;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as usize;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    Parts {
+        year,
+        month,
+        day,
+        hour: rem / 3600,
+        minute: rem % 3600 / 60,
+        second: rem % 60,
+        // 1970-01-01 was a Thursday
+        weekday: (days % 7) as usize,
+    }
+}
+
+/// The format used by `Date` and `Last-Modified`, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(time: SystemTime) -> String {
+    let p = parts(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[p.weekday],
+        p.day,
+        MONTHS[p.month - 1],
+        p.year,
+        p.hour,
+        p.minute,
+        p.second
+    )
+}
+
+/// The Common Log Format timestamp, e.g. `06/Nov/1994:08:49:37 +0000`.
+pub fn log_date(time: SystemTime) -> String {
+    let p = parts(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        p.day,
+        MONTHS[p.month - 1],
+        p.year,
+        p.hour,
+        p.minute,
+        p.second
+    )
+}
+
+/// ISO 8601, e.g. `1994-11-06T08:49:37Z`.
+pub fn iso8601(time: SystemTime) -> String {
+    let p = parts(time);
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        p.year, p.month, p.day, p.hour, p.minute, p.second
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_known_instant() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", http_date(time));
+        assert_eq!("06/Nov/1994:08:49:37 +0000", log_date(time));
+        assert_eq!("1994-11-06T08:49:37Z", iso8601(time));
+    }
+
+    #[test]
+    fn handles_leap_days() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+
+        assert_eq!("2000-02-29T00:00:00Z", iso8601(time));
+    }
+}
@@ -0,0 +1,217 @@
+//! Everything that can go wrong while answering a request, and the pages sent for it.
+
+use crate::body::BodyError;
+use crate::files;
+use crate::http::{self, ParseError, Response};
+use crate::template::{escape_html, TemplateError};
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ServerError {
+    /// The request could not be read or understood.
+    Parse(ParseError),
+    /// The connection failed while reading or writing.
+    Io(io::Error),
+    /// The client went quiet for longer than the read timeout.
+    Timeout,
+    /// Nothing answers to this path.
+    NotFound,
+    /// The path exists, but only for these methods.
+    MethodNotAllowed(Vec<String>),
+    /// A handler failed or panicked.
+    Handler(String),
+}
+
+impl ServerError {
+    /// The status code to answer with, or `None` if the client can't be answered.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ServerError::Parse(e) => e.status(),
+            ServerError::Io(_) => None,
+            ServerError::Timeout => Some(408),
+            ServerError::NotFound => Some(404),
+            ServerError::MethodNotAllowed(_) => Some(405),
+            ServerError::Handler(_) => Some(500),
+        }
+    }
+
+    /// Turns a panic payload caught from a handler into an error.
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> ServerError {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "handler panicked".to_string(),
+            },
+        };
+        ServerError::Handler(message)
+    }
+
+    /// Builds the response for this error from the first page in `pages` that has its
+    /// status and can be read, or from a plain built-in page.
+    ///
+    /// Errors the client can't be answered for get a 500, though there's usually
+    /// nobody left to send it to.
+    pub fn to_response(&self, pages: &[ErrorPages]) -> Response {
+        let status = self.status().unwrap_or(500);
+
+        let page = pages.iter().find_map(|pages| pages.read(status));
+        let response = match page {
+            Some((path, contents)) => Response::new(status)
+                .with_header("Content-Type", files::content_type(&path))
+                .with_body(contents),
+            None => Response::html(status, self.builtin_page(status)),
+        };
+
+        match self {
+            ServerError::MethodNotAllowed(allowed) => {
+                response.with_header("Allow", allowed.join(", "))
+            }
+            _ => response,
+        }
+    }
+
+    fn builtin_page(&self, status: u16) -> String {
+        let title = format!("{status} {}", http::reason_phrase(status));
+        // the details of a server-side failure are for the log, not the client
+        let detail = match status {
+            400..=499 => format!("<p>{}</p>\n", escape_html(&self.to_string())),
+            _ => String::new(),
+        };
+
+        format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n{detail}</body>\n</html>\n"
+        )
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerError::Parse(e) => write!(f, "{e}"),
+            ServerError::Io(e) => write!(f, "I/O error: {e}"),
+            ServerError::Timeout => write!(f, "timed out waiting for the request"),
+            ServerError::NotFound => write!(f, "no such page"),
+            ServerError::MethodNotAllowed(allowed) => {
+                write!(f, "method not allowed, use {}", allowed.join(" or "))
+            }
+            ServerError::Handler(message) => write!(f, "handler failed: {message}"),
+        }
+    }
+}
+
+impl Error for ServerError {}
+
+fn is_timeout(e: &io::Error) -> bool {
+    // a read timeout shows up as WouldBlock on Unix and TimedOut on Windows
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+impl From<ParseError> for ServerError {
+    fn from(e: ParseError) -> ServerError {
+        match e {
+            ParseError::Io(e) | ParseError::Body(BodyError::Io(e)) if is_timeout(&e) => {
+                ServerError::Timeout
+            }
+            e => ServerError::Parse(e),
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> ServerError {
+        if is_timeout(&e) {
+            ServerError::Timeout
+        } else {
+            ServerError::Io(e)
+        }
+    }
+}
+
+impl From<TemplateError> for ServerError {
+    fn from(e: TemplateError) -> ServerError {
+        ServerError::Handler(e.to_string())
+    }
+}
+
+/// Pages sent in place of the built-in error pages, by status.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorPages<'a> {
+    pages: &'a HashMap<u16, PathBuf>,
+    base: Option<&'a Path>,
+}
+
+impl<'a> ErrorPages<'a> {
+    /// Relative page paths are resolved against `base` when there is one.
+    pub fn new(pages: &'a HashMap<u16, PathBuf>, base: Option<&'a Path>) -> ErrorPages<'a> {
+        ErrorPages { pages, base }
+    }
+
+    // a missing or unreadable page is logged and skipped rather than failing the response
+    fn read(&self, status: u16) -> Option<(PathBuf, Vec<u8>)> {
+        let page = self.pages.get(&status)?;
+        let path = match &self.base {
+            Some(base) => base.join(page),
+            None => page.clone(),
+        };
+
+        match fs::read(&path) {
+            Ok(contents) => Some((path, contents)),
+            Err(e) => {
+                eprintln!("Failed to read error page {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_errors_to_statuses() {
+        let timeout = io::Error::new(io::ErrorKind::WouldBlock, "read timed out");
+        assert_eq!(
+            Some(408),
+            ServerError::from(ParseError::Io(timeout)).status()
+        );
+
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(None, ServerError::from(ParseError::Io(reset)).status());
+
+        assert_eq!(
+            Some(413),
+            ServerError::from(ParseError::Body(BodyError::TooLarge)).status()
+        );
+        assert_eq!(Some(500), ServerError::Handler("oops".into()).status());
+    }
+
+    #[test]
+    fn falls_back_to_builtin_page() {
+        let pages = HashMap::from([(404, PathBuf::from("/nonexistent/404.html"))]);
+
+        let response = ServerError::NotFound.to_response(&[ErrorPages::new(&pages, None)]);
+
+        assert_eq!(404, response.status);
+        assert!(String::from_utf8_lossy(&response.body).contains("<h1>404 Not Found</h1>"));
+    }
+
+    #[test]
+    fn hides_handler_details() {
+        let response = ServerError::Handler("secret path".into()).to_response(&[]);
+
+        assert_eq!(500, response.status);
+        assert!(!String::from_utf8_lossy(&response.body).contains("secret"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Serving static files from a document root.
+
+use crate::date;
+use crate::http::{self, Request, Response};
+use crate::listing;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Serves the file `request.path` maps to under `root`, or `None` if there isn't one,
+/// for a `GET` or a `HEAD`.
+///
+/// A directory is served through its `index.html`, or if it has none and `list` is
+/// set, through a generated listing; without the trailing slash the client is
+/// redirected first so relative links in the page resolve correctly. Names starting
+/// with `.` are only served with `dotfiles` set.
+pub fn serve(root: &Path, request: &Request, list: bool, dotfiles: bool) -> Option<Response> {
+    if request.method != "GET" && request.method != "HEAD" {
+        return None;
+    }
+    let segments = http::path_segments(&request.path)?;
+    if !dotfiles && segments.iter().any(|segment| segment.starts_with('.')) {
+        return None;
+    }
+
+    let path = resolve(root, &request.path)?;
+    let metadata = fs::metadata(&path).ok()?;
+
+    if metadata.is_dir() {
+        if !request.path.ends_with('/') {
+            return Some(Response::new(301).with_header("Location", directory_location(request)?));
+        }
+        let index = file_response(&path.join("index.html"));
+        if index.is_none() && list {
+            return listing::render(&path, request).ok();
+        }
+        return index;
+    }
+
+    file_response(&path)
+}
+
+/// Maps a percent-encoded URL path onto `root`, refusing anything that would escape it.
+pub fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    path.extend(http::path_segments(url_path)?);
+    Some(path)
+}
+
+// the normalized path with a trailing slash and the query kept; never starting
+// with `//`, which a browser would take for another host (`//example.com/`)
+fn directory_location(request: &Request) -> Option<String> {
+    let mut location = String::from("/");
+    for segment in http::path_segments(&request.path)? {
+        location.push_str(&http::percent_encode(&segment));
+        location.push('/');
+    }
+    if !request.query.is_empty() {
+        location.push('?');
+        location.push_str(&request.query);
+    }
+    Some(location)
+}
+
+fn file_response(path: &Path) -> Option<Response> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let contents = fs::read(path).ok()?;
+
+    let mut response = Response::new(200)
+        .with_header("Content-Type", content_type(path))
+        .with_body(contents);
+    if let Ok(modified) = metadata.modified() {
+        response = response.with_header("Last-Modified", date::http_date(modified));
+    }
+
+    Some(response)
+}
+
+/// Guesses a `Content-Type` from the file extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt" | "md" | "rs" | "toml") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_to_leave_the_root() {
+        let root = Path::new("/srv/site");
+
+        assert_eq!(
+            Some(PathBuf::from("/srv/site/a b/c.txt")),
+            resolve(root, "/a%20b/./c.txt")
+        );
+        assert_eq!(None, resolve(root, "/a/../../etc/passwd"));
+        assert_eq!(None, resolve(root, "/%2e%2e/etc/passwd"));
+    }
+}
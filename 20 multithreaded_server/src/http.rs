@@ -0,0 +1,524 @@
+//! Parsing of HTTP/1.1 requests and serialization of responses.
+
+use crate::body::{self, Body, BodyConfig, BodyError};
+use crate::json;
+use crate::session::Session;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::net::SocketAddr;
+
+// upper bounds for the request line plus headers, so a client can't make us buffer forever
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// Header fields in the order they were received; names compare case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Returns the first value for `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.fields
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Adds a field, keeping any existing fields with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.fields.push((name.into(), value.into()));
+    }
+
+    /// Replaces every field named `name` with a single value.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.fields.push((name, value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fields.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// The percent-encoded path, without the query string.
+    pub path: String,
+    /// Everything after `?`, or an empty string.
+    pub query: String,
+    pub version: String,
+    pub headers: Headers,
+    pub body: Body,
+    /// The client's address, filled in by the server when it is known.
+    pub peer: Option<SocketAddr>,
+    /// Filled in before routing when the router has sessions enabled.
+    pub session: Option<Session>,
+}
+
+impl Request {
+    /// Reads the request line and headers, then extracts the body according to its
+    /// `Content-Type`.
+    pub fn read_from<R: BufRead>(
+        reader: &mut R,
+        config: &BodyConfig,
+    ) -> Result<Request, ParseError> {
+        let (request_line, headers) = read_head(reader)?;
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(m), Some(t), Some(v), None) if !m.is_empty() && t.starts_with('/') => {
+                    (m, t, v)
+                }
+                _ => return Err(ParseError::Malformed("invalid request line")),
+            };
+
+        if !version.starts_with("HTTP/1.") {
+            return Err(ParseError::UnsupportedVersion);
+        }
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        let body = body::read_body(method, &headers, reader, config)?;
+
+        Ok(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            version: version.to_string(),
+            headers,
+            body,
+            peer: None,
+            session: None,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Decodes the query string the same way as a url-encoded form.
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        parse_urlencoded(&self.query)
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v)
+    }
+}
+
+/// Reads the first line of a request or response and the header fields after it.
+///
+/// Fails with [`ParseError::ConnectionClosed`] if the stream ends before anything
+/// was read.
+pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> Result<(String, Headers), ParseError> {
+    let first_line = read_line(reader)?.ok_or(ParseError::ConnectionClosed)?;
+
+    let mut headers = Headers::new();
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::Malformed("unexpected end of headers"))?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.fields.len() == MAX_HEADERS {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(ParseError::Malformed("header without ':'"))?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ParseError::Malformed("invalid header name"));
+        }
+        headers.append(name, value.trim());
+    }
+
+    Ok((first_line, headers))
+}
+
+// reads one CRLF (or bare LF) terminated line, returning None on a clean EOF
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        // either the line is too long or the client went away mid-line
+        return if read > MAX_LINE_LEN {
+            Err(ParseError::HeadersTooLarge)
+        } else {
+            Err(ParseError::Malformed("unexpected end of line"))
+        };
+    }
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::Malformed("message head is not UTF-8"))
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The client closed the connection before sending anything.
+    ConnectionClosed,
+    Io(io::Error),
+    Malformed(&'static str),
+    UnsupportedVersion,
+    HeadersTooLarge,
+    Body(BodyError),
+}
+
+impl ParseError {
+    /// The status code to answer with, if the connection is still usable.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ParseError::ConnectionClosed | ParseError::Io(_) => None,
+            ParseError::Malformed(_) => Some(400),
+            ParseError::UnsupportedVersion => Some(505),
+            ParseError::HeadersTooLarge => Some(431),
+            ParseError::Body(e) => e.status(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed"),
+            ParseError::Io(e) => write!(f, "I/O error: {e}"),
+            ParseError::Malformed(reason) => write!(f, "malformed request: {reason}"),
+            ParseError::UnsupportedVersion => write!(f, "unsupported HTTP version"),
+            ParseError::HeadersTooLarge => write!(f, "request head too large"),
+            ParseError::Body(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+impl From<BodyError> for ParseError {
+    fn from(e: BodyError) -> ParseError {
+        ParseError::Body(e)
+    }
+}
+
+/// Writes a body of unknown length directly to the connection, e.g. an event stream.
+pub type StreamFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// When set, replaces `body`; the connection is closed once it returns.
+    pub stream: Option<StreamFn>,
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("body", &String::from_utf8_lossy(&self.body))
+            .field("stream", &self.stream.is_some())
+            .finish()
+    }
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            stream: None,
+        }
+    }
+
+    pub fn html(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    pub fn json(status: u16, value: &json::Value) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(value.to_string())
+    }
+
+    /// A response whose body is produced by `f` writing to the connection.
+    pub fn stream<F>(status: u16, f: F) -> Response
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        Response {
+            stream: Some(Box::new(f)),
+            ..Response::new(status)
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Writes the status line, headers and body.
+    ///
+    /// `Content-Length` is always set, except for streamed bodies, which are
+    /// delimited by closing the connection instead.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, true)
+    }
+
+    /// Writes the status line and headers alone, with the `Content-Length` the
+    /// body would have: the answer to a `HEAD`.
+    pub fn write_head_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        self.write(writer, false)
+    }
+
+    fn write<W: Write>(self, writer: &mut W, with_body: bool) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        );
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+
+        match self.stream {
+            Some(stream) => {
+                head.push_str("Connection: close\r\n\r\n");
+                writer.write_all(head.as_bytes())?;
+                writer.flush()?;
+                if with_body {
+                    stream(writer)?;
+                }
+            }
+            None => {
+                head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+                writer.write_all(head.as_bytes())?;
+                if with_body {
+                    writer.write_all(&self.body)?;
+                }
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+/// Decodes `%XX` escapes; invalid escapes are kept as-is and invalid UTF-8 is replaced.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let decoded = match bytes.get(i..i + 3) {
+            Some([b'%', hi, lo]) => std::str::from_utf8(&[*hi, *lo])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match decoded {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Escapes everything but unreserved characters, so `segment` can be used as one
+/// path segment of a URL.
+pub fn percent_encode(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// The segments of the percent-encoded `path`, as they are mapped onto files:
+/// decoded, without empty or `.` segments, and `None` if any is `..` or holds a
+/// `\` or NUL.
+pub fn path_segments(path: &str) -> Option<Vec<String>> {
+    percent_decode(path)
+        .split('/')
+        .filter(|segment| !matches!(*segment, "" | "."))
+        .map(|segment| match segment {
+            ".." => None,
+            s if s.contains(['\\', '\0']) => None,
+            s => Some(s.to_string()),
+        })
+        .collect()
+}
+
+/// Whether the percent-encoded `path` is `prefix` or below it, comparing whole
+/// segments: `/admin` covers `/admin` and `/admin/users` but not `/administrator`.
+/// A path [`path_segments`] refuses is within nothing.
+pub fn path_within(path: &str, prefix: &str) -> bool {
+    // normalized first, or `/%61dmin` and `//admin` would slip past a check for
+    // `/admin` and still be served from it
+    let Some(path) = path_segments(path) else {
+        return false;
+    };
+    let prefix = prefix.split('/').filter(|segment| !segment.is_empty());
+
+    let mut path = path.iter();
+    prefix
+        .into_iter()
+        .all(|segment| path.next().is_some_and(|s| s == segment))
+}
+
+/// Parses `application/x-www-form-urlencoded` data (also used for query strings).
+pub fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(&key.replace('+', " ")),
+                percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let raw =
+            b"GET /search?q=rust+book&page=2 HTTP/1.1\r\nHost: localhost\r\nX-Test:  yes \r\n\r\n";
+        let request = Request::read_from(&mut &raw[..], &BodyConfig::default()).unwrap();
+
+        assert_eq!("GET", request.method);
+        assert_eq!("/search", request.path);
+        assert_eq!(Some("rust book".to_string()), request.query_param("q"));
+        assert_eq!(Some("yes"), request.header("x-test"));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let err =
+            Request::read_from(&mut &b"hello\r\n\r\n"[..], &BodyConfig::default()).unwrap_err();
+
+        assert_eq!(Some(400), err.status());
+    }
+
+    #[test]
+    fn compares_normalized_paths() {
+        assert!(path_within("/admin", "/admin/"));
+        assert!(path_within("//admin/./users", "/admin"));
+        assert!(path_within("/%61dmin/users", "/admin"));
+        assert!(!path_within("/administrator", "/admin"));
+        assert!(!path_within("/admin/../admin", "/admin"));
+        assert!(path_within("/anything", "/"));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!("a b/é%zz", percent_decode("a%20b%2F%C3%A9%zz"));
+        assert_eq!("a%20b%2F%C3%A9.txt", percent_encode("a b/é.txt"));
+    }
+}
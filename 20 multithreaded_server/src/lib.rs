@@ -1,16 +1,119 @@
+pub mod admin;
+pub mod body;
+pub mod client;
+pub mod cookie;
+pub mod date;
+pub mod error;
+pub mod files;
+pub mod http;
+pub mod json;
+pub mod listener;
+pub mod listing;
+pub mod middleware;
+pub mod reload;
+pub mod routes;
+pub mod server;
+pub mod session;
+pub mod sse;
+pub mod task;
+pub mod template;
+pub mod vhost;
+
+// the integration tests' temporary directories, for the unit tests too
+#[cfg(test)]
+#[path = "../tests/common/temp_dir.rs"]
+mod temp_dir;
+
 pub mod hello {
+    use crate::task::{JoinHandle, Task};
     use std::{
-        sync::{mpsc, Arc, Mutex},
+        cell::RefCell,
+        future::Future,
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
         thread,
+        time::SystemTime,
     };
 
     pub struct ThreadPool {
-        workers: Vec<Worker>,
-        sender: Option<mpsc::Sender<Job>>,
+        // behind a mutex so the pool can be resized while jobs are being sent to it
+        workers: Mutex<Vec<Worker>>,
+        // spawned futures only hold a weak reference, so dropping this one still
+        // closes the channel and stops the workers
+        sender: Option<Arc<mpsc::Sender<Message>>>,
+        // kept to hand to workers added by `resize`
+        receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+        size: AtomicUsize,
+        next_id: AtomicUsize,
+        monitor: PoolMonitor,
     }
 
     type Job = Box<dyn FnOnce() + Send + 'static>;
 
+    // a worker that takes `Stop` off the queue exits, which is how the pool shrinks
+    enum Message {
+        Job(Job),
+        Stop,
+    }
+
+    /// What a worker is doing, as seen through a [`PoolMonitor`].
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct WorkerStatus {
+        pub id: usize,
+        /// When the current job started, or `None` while the worker is idle.
+        pub busy_since: Option<SystemTime>,
+        /// What the current job said it is doing, see [`describe_job`].
+        pub job: Option<String>,
+    }
+
+    /// A view of a pool's workers that can be read from any thread.
+    #[derive(Clone, Default)]
+    pub struct PoolMonitor {
+        statuses: Arc<Mutex<Vec<WorkerStatus>>>,
+        queued: Arc<AtomicUsize>,
+    }
+
+    impl PoolMonitor {
+        pub fn workers(&self) -> Vec<WorkerStatus> {
+            self.statuses
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone()
+        }
+
+        /// Jobs waiting for a free worker.
+        pub fn queued(&self) -> usize {
+            self.queued.load(Ordering::SeqCst)
+        }
+
+        fn update(&self, id: usize, f: impl FnOnce(&mut WorkerStatus)) {
+            let mut statuses = self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(status) = statuses.iter_mut().find(|s| s.id == id) {
+                f(status);
+            }
+        }
+    }
+
+    thread_local! {
+        // the pool and worker id of the current thread, if it is a worker
+        static CURRENT: RefCell<Option<(PoolMonitor, usize)>> = const { RefCell::new(None) };
+    }
+
+    /// Says what the job running on this thread is doing, e.g. which request it
+    /// serves, for anyone looking at the pool's [`PoolMonitor`]. Does nothing when
+    /// called outside a pool.
+    pub fn describe_job(description: impl Into<String>) {
+        CURRENT.with(|current| {
+            if let Some((monitor, id)) = &*current.borrow() {
+                let description = description.into();
+                monitor.update(*id, |status| status.job = Some(description));
+            }
+        });
+    }
+
     impl ThreadPool {
         // (note the doc comments below: `cargo doc --open`)
 
@@ -33,16 +136,62 @@ pub mod hello {
 
             let mut workers = Vec::with_capacity(size);
 
+            let monitor = PoolMonitor::default();
+
             for id in 0..size {
-                workers.push(Worker::new(id, Arc::clone(&receiver)));
+                workers.push(Worker::new(id, Arc::clone(&receiver), monitor.clone()));
             }
 
             ThreadPool {
-                workers,
-                sender: Some(sender),
+                workers: Mutex::new(workers),
+                sender: Some(Arc::new(sender)),
+                receiver,
+                size: AtomicUsize::new(size),
+                next_id: AtomicUsize::new(size),
+                monitor,
             }
         }
 
+        /// The number of workers the pool has, or will have once a shrink is done.
+        pub fn size(&self) -> usize {
+            self.size.load(Ordering::SeqCst)
+        }
+
+        /// Grows or shrinks the pool to `size` workers.
+        ///
+        /// New workers start right away. Surplus workers are told to stop through
+        /// the queue, so they first finish the job they are on and everything
+        /// queued before the resize.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the size is zero.
+        pub fn resize(&self, size: usize) {
+            assert!(size > 0);
+
+            let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+            // workers stopped by an earlier shrink can be joined by now
+            workers.retain(|worker| !worker.thread.as_ref().is_some_and(|t| t.is_finished()));
+
+            let current = self.size.swap(size, Ordering::SeqCst);
+            for _ in current..size {
+                let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+                workers.push(Worker::new(
+                    id,
+                    Arc::clone(&self.receiver),
+                    self.monitor.clone(),
+                ));
+            }
+            for _ in size..current {
+                self.sender.as_ref().unwrap().send(Message::Stop).unwrap();
+            }
+        }
+
+        /// A handle for watching the workers, e.g. from an admin page.
+        pub fn monitor(&self) -> PoolMonitor {
+            self.monitor.clone()
+        }
+
         // the idea is for this to work similarly to the standard library’s thread::spawn function
         // spawn uses FnOnce as the trait bound on F
         // also, the request will only be processed once
@@ -54,7 +203,45 @@ pub mod hello {
         {
             let job = Box::new(f);
 
-            self.sender.as_ref().unwrap().send(job).unwrap();
+            self.monitor.queued.fetch_add(1, Ordering::SeqCst);
+            self.sender
+                .as_ref()
+                .unwrap()
+                .send(Message::Job(job))
+                .unwrap();
+        }
+    }
+
+    impl ThreadPool {
+        /// Runs `future` on the pool's workers, alongside the jobs from `execute`.
+        ///
+        /// A worker polls the future until it has to wait, then moves on to other
+        /// jobs; the future's waker queues it again once it can make progress. So a
+        /// future waiting on a [`crate::task::sleep`] or [`crate::task::channel`]
+        /// doesn't hold up a worker, but one that blocks the thread does.
+        ///
+        /// Futures still waiting when the pool is dropped are never polled again,
+        /// but they live as long as their waker does: one in a [`crate::task::sleep`]
+        /// is freed at its deadline, one in a [`crate::task::channel`] once a value
+        /// is sent or the last sender is dropped, and one whose waker is kept
+        /// forever is never freed.
+        pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send + 'static,
+        {
+            let sender = Arc::downgrade(self.sender.as_ref().unwrap());
+            let queued = Arc::clone(&self.monitor.queued);
+
+            Task::spawn(future, move |task| {
+                let Some(sender) = sender.upgrade() else {
+                    return;
+                };
+                queued.fetch_add(1, Ordering::SeqCst);
+                if sender.send(Message::Job(Box::new(|| task.run()))).is_err() {
+                    queued.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
         }
     }
 
@@ -63,7 +250,8 @@ pub mod hello {
             // must drop sender first so that workers know to stop
             drop(self.sender.take());
 
-            for worker in &mut self.workers {
+            let workers = self.workers.get_mut().unwrap_or_else(|e| e.into_inner());
+            for worker in workers {
                 println!("Shutting down worker {}", worker.id);
 
                 // we need to move the thread out of the Worker instance that owns thread
@@ -81,26 +269,65 @@ pub mod hello {
     }
 
     impl Worker {
-        fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+        fn new(
+            id: usize,
+            receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+            monitor: PoolMonitor,
+        ) -> Worker {
+            monitor.statuses.lock().unwrap().push(WorkerStatus {
+                id,
+                busy_since: None,
+                job: None,
+            });
+
             // if the OS can't create a thread the whole program will panic
             // for simplicity, we're not handling this case
             // std::thread::Builder::spawn is an alternative that returns a Result
-            let thread = thread::spawn(move || loop {
-                // lock() can fail if the mutex is poisoned
-                // this can happen if a thread panics while holding the lock
-                let message = receiver.lock().unwrap().recv();
+            let thread = thread::spawn(move || {
+                CURRENT.with(|current| *current.borrow_mut() = Some((monitor.clone(), id)));
 
-                match message {
-                    Ok(job) => {
-                        println!("Worker {id} got a job; executing.");
+                loop {
+                    // lock() can fail if the mutex is poisoned
+                    // this can happen if a thread panics while holding the lock
+                    let message = receiver.lock().unwrap().recv();
 
-                        job();
-                    }
-                    Err(_) => {
-                        println!("Worker {id} disconnected; shutting down.");
-                        break;
+                    match message {
+                        Ok(Message::Job(job)) => {
+                            println!("Worker {id} got a job; executing.");
+
+                            monitor.queued.fetch_sub(1, Ordering::SeqCst);
+                            monitor.update(id, |status| {
+                                status.busy_since = Some(SystemTime::now());
+                                status.job = None;
+                            });
+
+                            // a panicking job fails alone, and the worker goes on
+                            // to the next as if it had returned
+                            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                eprintln!("Worker {id} had a job panic.");
+                            }
+
+                            monitor.update(id, |status| {
+                                status.busy_since = None;
+                                status.job = None;
+                            });
+                        }
+                        Ok(Message::Stop) => {
+                            println!("Worker {id} no longer needed; shutting down.");
+                            break;
+                        }
+                        Err(_) => {
+                            println!("Worker {id} disconnected; shutting down.");
+                            break;
+                        }
                     }
                 }
+
+                monitor
+                    .statuses
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .retain(|status| status.id != id);
             });
 
             Worker {
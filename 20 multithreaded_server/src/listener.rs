@@ -0,0 +1,386 @@
+//! The endpoints a server listens on: TCP over IPv4 or IPv6, Unix domain sockets,
+//! and listening sockets inherited from a parent process (socket activation).
+
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::{env, fs, process};
+
+// how long to wait after a failed accept before the next; out of descriptors,
+// accepting again straight away would only fail again, as fast as it could
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+// systemd passes inherited sockets starting at this descriptor
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+// the inherited descriptors already made into listeners, each of which closes
+// its own when dropped, so none may be taken twice
+#[cfg(unix)]
+static CLAIMED: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+
+/// Where to listen, written as `127.0.0.1:7878`, `[::1]:7878`, `unix:/run/app.sock`
+/// or `fd:3`; an `fd:` must be one of the sockets a supervisor passed in (see
+/// [`inherited`]).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// An already listening socket, e.g. handed over by a supervisor.
+    #[cfg(unix)]
+    Fd(RawFd),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Endpoint, String> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = s.strip_prefix("unix:") {
+                if path.is_empty() {
+                    return Err("unix: needs a socket path".to_string());
+                }
+                return Ok(Endpoint::Unix(PathBuf::from(path)));
+            }
+            if let Some(fd) = s.strip_prefix("fd:") {
+                return fd
+                    .parse()
+                    .map(Endpoint::Fd)
+                    .map_err(|_| format!("invalid file descriptor in {s}"));
+            }
+        }
+
+        s.parse()
+            .map(Endpoint::Tcp)
+            .map_err(|_| format!("invalid listen address {s}"))
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            Endpoint::Fd(fd) => write!(f, "fd:{fd}"),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        /// Whether we created the socket file and so should remove it again.
+        owned: bool,
+    },
+}
+
+impl Listener {
+    pub fn bind(endpoint: &Endpoint) -> io::Result<Listener> {
+        match endpoint {
+            Endpoint::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(Listener::Unix {
+                    listener: UnixListener::bind(path)?,
+                    path: path.clone(),
+                    owned: true,
+                })
+            }
+            #[cfg(unix)]
+            Endpoint::Fd(fd) => take_inherited(*fd, inherited_fds()),
+        }
+    }
+
+    /// Takes ownership of an open listening socket, working out whether it is TCP or Unix.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open descriptor that nothing else owns or will close: the
+    /// listener closes it when dropped. It is left open if this fails.
+    #[cfg(unix)]
+    pub unsafe fn from_fd(fd: RawFd) -> io::Result<Listener> {
+        // SAFETY: the caller hands over ownership of `fd`; it is closed when the
+        // listener is dropped, and given back with into_raw_fd if it turns out not to be TCP
+        let tcp = unsafe { TcpListener::from_raw_fd(fd) };
+        if tcp.local_addr().is_ok() {
+            return Ok(Listener::Tcp(tcp));
+        }
+        let fd = tcp.into_raw_fd();
+
+        // SAFETY: as above
+        let unix = unsafe { UnixListener::from_raw_fd(fd) };
+        match unix.local_addr() {
+            Ok(addr) => Ok(Listener::Unix {
+                path: addr
+                    .as_pathname()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+                listener: unix,
+                owned: false,
+            }),
+            Err(e) => {
+                // not ours to close if it isn't a socket we understand
+                let _ = unix.into_raw_fd();
+                Err(io::Error::new(
+                    e.kind(),
+                    format!("fd {fd} is not a listening socket: {e}"),
+                ))
+            }
+        }
+    }
+
+    /// What the listener is actually bound to, e.g. the real port after binding to port 0.
+    pub fn local_endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Endpoint::Tcp),
+            #[cfg(unix)]
+            Listener::Unix { path, .. } => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+
+    /// Waits for the next connection, returning the client's address when there is one.
+    pub fn accept(&self) -> io::Result<(Connection, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer) = listener.accept()?;
+                Ok((Connection::Tcp(stream), Some(peer)))
+            }
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let (stream, _) = listener.accept()?;
+                Ok((Connection::Unix(stream), None))
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix {
+            path, owned: true, ..
+        } = self
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// a socket file left behind by a crashed process would make bind fail, but one that
+// still accepts connections belongs to a live server and must be left alone
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if UnixStream::connect(path).is_err() {
+                fs::remove_file(path)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Listening sockets passed in by a supervisor such as systemd, following the
+/// `LISTEN_PID`/`LISTEN_FDS` convention, except those already taken by an `fd:`
+/// endpoint or an earlier call. Empty when none were passed.
+#[cfg(unix)]
+pub fn inherited() -> io::Result<Vec<Listener>> {
+    let fds = inherited_fds();
+    let unclaimed: Vec<RawFd> = {
+        let claimed = CLAIMED.lock().unwrap_or_else(|e| e.into_inner());
+        fds.clone().filter(|fd| !claimed.contains(fd)).collect()
+    };
+
+    unclaimed
+        .into_iter()
+        .map(|fd| take_inherited(fd, fds.clone()))
+        .collect()
+}
+
+// the descriptors a supervisor passed to this process, if any
+#[cfg(unix)]
+fn inherited_fds() -> Range<RawFd> {
+    let none = LISTEN_FDS_START..LISTEN_FDS_START;
+    let count: RawFd = match env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()) {
+        Some(count) if count > 0 => count,
+        _ => return none,
+    };
+
+    // the variables are inherited by our children too, so check they were meant for us
+    if let Some(pid) = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok())
+    {
+        if pid != process::id() {
+            return none;
+        }
+    }
+
+    LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count)
+}
+
+// makes a listener of `fd`, one of the `inherited` descriptors, unless it has
+// been already
+#[cfg(unix)]
+fn take_inherited(fd: RawFd, inherited: Range<RawFd>) -> io::Result<Listener> {
+    claim(fd, inherited)?;
+
+    // SAFETY: a descriptor the supervisor passed in belongs to this process, and
+    // having claimed it, to nothing else in it
+    let listener = unsafe { Listener::from_fd(fd) };
+    if listener.is_err() {
+        CLAIMED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|&claimed| claimed != fd);
+    }
+    listener
+}
+
+#[cfg(unix)]
+fn claim(fd: RawFd, inherited: Range<RawFd>) -> io::Result<()> {
+    if !inherited.contains(&fd) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("fd {fd} was not passed in through LISTEN_FDS"),
+        ));
+    }
+
+    let mut claimed = CLAIMED.lock().unwrap_or_else(|e| e.into_inner());
+    if claimed.contains(&fd) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("fd {fd} is already being listened on"),
+        ));
+    }
+    claimed.push(fd);
+    Ok(())
+}
+
+/// An accepted connection, read from and written to the same way whatever its kind.
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Connects to `endpoint` and drops the connection; used to wake a blocked `accept`.
+pub(crate) fn poke(endpoint: &Endpoint) {
+    match endpoint {
+        Endpoint::Tcp(addr) => {
+            let mut addr = *addr;
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+                    SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            let _ = TcpStream::connect(addr);
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            let _ = UnixStream::connect(path);
+        }
+        // local_endpoint never reports an Fd
+        #[cfg(unix)]
+        Endpoint::Fd(_) => {}
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_endpoints() {
+        assert_eq!(
+            Ok(Endpoint::Tcp("127.0.0.1:7878".parse().unwrap())),
+            "127.0.0.1:7878".parse()
+        );
+        assert_eq!(
+            Ok(Endpoint::Tcp("[::1]:7878".parse().unwrap())),
+            "[::1]:7878".parse()
+        );
+        assert_eq!(
+            Ok(Endpoint::Unix(PathBuf::from("/run/app.sock"))),
+            "unix:/run/app.sock".parse()
+        );
+        assert_eq!(Ok(Endpoint::Fd(3)), "fd:3".parse());
+        assert!("localhost".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn claims_inherited_fds_once() {
+        // far above anything the test harness has open
+        let inherited = 1000..1003;
+
+        assert!(claim(1001, inherited.clone()).is_ok());
+        let again = claim(1001, inherited.clone()).unwrap_err();
+        assert_eq!(io::ErrorKind::AddrInUse, again.kind());
+        let outside = claim(1003, inherited).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, outside.kind());
+    }
+}
@@ -0,0 +1,216 @@
+//! Generated listings of directories that have no `index.html`.
+
+use crate::date;
+use crate::http::{self, Request, Response};
+use crate::json::Value;
+use crate::template::escape_html;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+        }
+    }
+}
+
+/// Lists `dir`, the directory `request.path` maps to, as HTML or, if the client
+/// prefers it in `Accept`, as JSON.
+///
+/// `?sort=name|size|modified` and `?order=asc|desc` pick the order; directories
+/// always come first. Hidden files (starting with `.`) are left out.
+pub fn render(dir: &Path, request: &Request) -> io::Result<Response> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        // follows symlinks, so a link to a directory is listed as one
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    let key = match request.query_param("sort").as_deref() {
+        Some("size") => SortKey::Size,
+        Some("modified") => SortKey::Modified,
+        _ => SortKey::Name,
+    };
+    let descending = request.query_param("order").as_deref() == Some("desc");
+    entries.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let order = if descending { order.reverse() } else { order };
+        b.is_dir.cmp(&a.is_dir).then(order)
+    });
+
+    let response = if prefers_json(request) {
+        Response::json(200, &to_json(request, &entries))
+    } else {
+        Response::html(200, to_html(request, &entries, key, descending))
+    };
+    // the same URL answers differently depending on Accept
+    Ok(response.with_header("Vary", "Accept"))
+}
+
+fn href(entry: &Entry) -> String {
+    let mut href = http::percent_encode(&entry.name);
+    if entry.is_dir {
+        href.push('/');
+    }
+    href
+}
+
+fn to_json(request: &Request, entries: &[Entry]) -> Value {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            Value::object([
+                ("name", entry.name.as_str().into()),
+                ("href", href(entry).into()),
+                (
+                    "type",
+                    if entry.is_dir { "directory" } else { "file" }.into(),
+                ),
+                ("size", entry.size.into()),
+                ("modified", entry.modified.map(date::iso8601).into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    Value::object([
+        ("path", http::percent_decode(&request.path).into()),
+        ("entries", entries.into()),
+    ])
+}
+
+fn to_html(request: &Request, entries: &[Entry], key: SortKey, descending: bool) -> String {
+    let path = escape_html(&http::percent_decode(&request.path));
+
+    // clicking the column the listing is sorted by flips the order
+    let header = |column: SortKey, label: &str| {
+        let order = if column == key && !descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        format!(
+            "<th><a href=\"?sort={}&amp;order={order}\">{label}</a></th>",
+            column.as_str()
+        )
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Index of {path}</title>\n</head>\n<body>\n<h1>Index of {path}</h1>\n\
+         <table>\n<thead><tr>{}{}{}</tr></thead>\n<tbody>\n",
+        header(SortKey::Name, "Name"),
+        header(SortKey::Size, "Size"),
+        header(SortKey::Modified, "Last modified"),
+    );
+
+    if request.path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let name = escape_html(&entry.name);
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry.modified.map(date::iso8601).unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{name}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>\n",
+            escape_html(&href(entry)),
+        ));
+    }
+
+    html.push_str("</tbody>\n</table>\n</body>\n</html>\n");
+    html
+}
+
+fn prefers_json(request: &Request) -> bool {
+    let Some(accept) = request.header("Accept") else {
+        return false;
+    };
+    quality(accept, "application/json") > quality(accept, "text/html")
+}
+
+// the q value `accept` gives `media_type`, taken from the most specific range matching it
+fn quality(accept: &str, media_type: &str) -> f32 {
+    let (kind, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+    let mut best: Option<(u8, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let range = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let specificity = if range == media_type {
+            2
+        } else if range == format!("{kind}/*") {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, q));
+        }
+    }
+
+    best.map_or(0.0, |(_, q)| q)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_with_accept() {
+        let accept = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+        assert_eq!(1.0, quality(accept, "text/html"));
+        assert_eq!(0.8, quality(accept, "application/json"));
+
+        let accept = "application/json, text/*;q=0.5";
+        assert_eq!(0.5, quality(accept, "text/html"));
+        assert_eq!(0.0, quality("image/png", "text/html"));
+    }
+}
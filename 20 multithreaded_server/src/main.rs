@@ -1,62 +1,101 @@
-use multithreaded_server::hello::ThreadPool;
-use std::fs;
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use std::thread;
+use multithreaded_server::listener::{Endpoint, Listener};
+use multithreaded_server::reload;
+use multithreaded_server::routes;
+use multithreaded_server::server::{Config, Server};
+use multithreaded_server::session::MemoryStore;
+use multithreaded_server::sse::Broadcaster;
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
 use std::time::Duration;
 
-// todo:
-// Add tests of the library’s functionality.
-// Change calls to unwrap to more robust error handling.
+fn parse_endpoint(arg: &str) -> Endpoint {
+    arg.parse().unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(1);
+    })
+}
 
 fn main() {
-    // port is arbitrary
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
-
-    // incoming returns an iterator that gives us a sequence of streams
-    // each stream represents an incoming connection to the server
-    // (the full request and response process)
-
-    // only two requests are accepted to demonstrate graceful shutdown
-    /// take is defined on the Iterator trait
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap(); // connection is dropped at the end of the scope
-
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+    // listen addresses come from the command line, e.g.
+    // `cargo run -- 127.0.0.1:7878 [::1]:7878 unix:/tmp/hello.sock --admin 127.0.0.1:7879`
+    // the rest of the config can come from a file, reread on SIGHUP (and on every
+    // change with --watch): `cargo run -- --config server.json --watch`
+    let mut admin = None;
+    let mut config_file = None;
+    let mut watch = false;
+    let mut endpoints = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("Problem parsing arguments: {arg} needs a value");
+                process::exit(1);
+            })
+        };
+        match arg.as_str() {
+            "--admin" => admin = Some(parse_endpoint(&value())),
+            "--config" => config_file = Some(PathBuf::from(value())),
+            "--watch" => watch = true,
+            _ => endpoints.push(parse_endpoint(&arg)),
+        }
     }
 
-    println!("Shutting down.");
-}
-
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
-
-    let (status_line, filename) = if buffer.starts_with(get) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+    let mut config = match &config_file {
+        Some(path) => reload::load(path).unwrap_or_else(|err| {
+            eprintln!("Problem reading {}: {err}", path.display());
+            process::exit(1);
+        }),
+        None => Config::default(),
     };
+    if admin.is_some() {
+        config.admin = admin;
+    }
 
-    let contents = fs::read_to_string(filename).unwrap();
+    // sockets handed over by a supervisor (socket activation) are used as well
+    #[cfg(unix)]
+    let mut listeners = multithreaded_server::listener::inherited().unwrap_or_else(|err| {
+        eprintln!("Problem taking over inherited sockets: {err}");
+        process::exit(1);
+    });
+    #[cfg(not(unix))]
+    let mut listeners = Vec::new();
 
-    let response = format!(
-        "{}\r\nContent-Length: {}\r\n\r\n{}",
-        status_line,
-        contents.len(),
-        contents
-    );
+    if endpoints.is_empty() && listeners.is_empty() {
+        // port is arbitrary
+        endpoints.push(Endpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], 7878))));
+    }
+    for endpoint in &endpoints {
+        listeners.push(Listener::bind(endpoint).unwrap_or_else(|err| {
+            eprintln!("Problem listening on {endpoint}: {err}");
+            process::exit(1);
+        }));
+    }
 
-    stream.write_all(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+    // made once, not for every router, so a reload keeps everyone's sessions and
+    // streams
+    let sessions = Arc::new(MemoryStore::new());
+    let events = Broadcaster::new(100);
+    let closing = events.clone();
+    let mut server = Server::listen(listeners, config, move |_| {
+        routes::router(
+            Duration::from_secs(5),
+            Arc::clone(&sessions),
+            events.clone(),
+        )
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("Problem starting the server: {err}");
+        process::exit(1);
+    })
+    // subscribers would otherwise keep their workers, and the shutdown, waiting
+    .with_shutdown_hook(move || closing.close());
+    if let Some(path) = config_file {
+        server = server.with_config_file(path, watch);
+    }
+
+    // only two requests are accepted to demonstrate graceful shutdown
+    server.run_for(2);
 }
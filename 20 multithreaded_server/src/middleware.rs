@@ -0,0 +1,407 @@
+//! Code that runs around every handler of a router: the [`Middleware`] trait and
+//! the middleware that comes with the server.
+
+use crate::http::{self, Request, Response};
+use crate::session;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Hooks run before and after the handler, added to a router with
+/// [`crate::server::Router::wrap`].
+///
+/// `before` hooks run in the order the middleware was added, `after` hooks in
+/// reverse, so the first middleware added sees the request first and the response last.
+pub trait Middleware: Send + Sync {
+    /// Returning a response skips the handler and any later middleware; the `after`
+    /// hooks of this and earlier middleware still run on it.
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        response
+    }
+}
+
+/// Runs `handler` inside `chain`.
+pub fn run<F>(chain: &[Box<dyn Middleware>], request: &mut Request, handler: F) -> Response
+where
+    F: FnOnce(&Request) -> Response,
+{
+    let mut entered = 0;
+    let mut early = None;
+    for middleware in chain {
+        entered += 1;
+        early = middleware.before(request);
+        if early.is_some() {
+            break;
+        }
+    }
+
+    let mut response = match early {
+        Some(response) => response,
+        None => handler(request),
+    };
+    for middleware in chain[..entered].iter().rev() {
+        response = middleware.after(request, response);
+    }
+    response
+}
+
+/// Cross-origin resource sharing: answers preflight requests and marks responses
+/// as readable by the allowed origins.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    max_age: Duration,
+    credentials: bool,
+}
+
+impl Cors {
+    /// Allows no origins until some are added with [`Cors::with_origin`].
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "POST".to_string()],
+            headers: vec!["Content-Type".to_string()],
+            max_age: Duration::from_secs(24 * 60 * 60),
+            credentials: false,
+        }
+    }
+
+    /// An origin such as `https://example.com`, or `*` for any.
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Cors {
+        self.origins.push(origin.into());
+        self
+    }
+
+    pub fn with_methods<I, S>(mut self, methods: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.methods = methods.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// The request headers scripts may send, beyond the ones always allowed.
+    pub fn with_headers<I, S>(mut self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How long browsers may cache a preflight answer.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Lets scripts send cookies and read the response. The allowed origin is then
+    /// always named explicitly, as browsers refuse `*` with credentials.
+    pub fn with_credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+
+    // the value for Access-Control-Allow-Origin, if `origin` is allowed
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.iter().any(|o| o == origin) {
+            Some(origin.to_string())
+        } else if self.origins.iter().any(|o| o == "*") {
+            Some(match self.credentials {
+                true => origin.to_string(),
+                false => "*".to_string(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn with_origin_headers(&self, response: Response, allowed: String) -> Response {
+        let mut response = response.with_header("Access-Control-Allow-Origin", allowed);
+        // the answer depends on the Origin, so caches must keep one copy per origin
+        response.headers.append("Vary", "Origin");
+        if self.credentials {
+            response = response.with_header("Access-Control-Allow-Credentials", "true");
+        }
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let preflight = request.method == "OPTIONS"
+            && request.headers.contains("Access-Control-Request-Method");
+        if !preflight {
+            return None;
+        }
+        let allowed = self.allow_origin(request.header("Origin")?)?;
+
+        let response = Response::new(204)
+            .with_header("Access-Control-Allow-Methods", self.methods.join(", "))
+            .with_header("Access-Control-Allow-Headers", self.headers.join(", "))
+            .with_header("Access-Control-Max-Age", self.max_age.as_secs().to_string());
+        Some(self.with_origin_headers(response, allowed))
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        // preflight answers already have their headers
+        if response.headers.contains("Access-Control-Allow-Origin") {
+            return response;
+        }
+        match request.header("Origin").and_then(|o| self.allow_origin(o)) {
+            Some(allowed) => self.with_origin_headers(response, allowed),
+            None => response,
+        }
+    }
+}
+
+/// Gives every request an id, in the `X-Request-Id` header of both the request
+/// and the response, keeping one sent by a proxy in front of the server.
+#[derive(Debug, Clone, Default)]
+pub struct RequestId;
+
+impl RequestId {
+    pub const HEADER: &'static str = "X-Request-Id";
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        // an incoming id ends up in logs, so only accept something tame
+        let valid = request.header(Self::HEADER).is_some_and(|id| {
+            !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+        });
+        if !valid {
+            request.headers.set(Self::HEADER, session::random_id());
+        }
+        None
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        match request.header(Self::HEADER) {
+            Some(id) => response.with_header(Self::HEADER, id),
+            None => response,
+        }
+    }
+}
+
+/// Headers that tell browsers to be stricter with the site's pages. Headers a
+/// handler set itself are left alone.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    hsts: Option<Duration>,
+    content_security_policy: Option<String>,
+    nosniff: bool,
+}
+
+impl SecurityHeaders {
+    /// `X-Content-Type-Options: nosniff` and a policy allowing only same-origin
+    /// resources; HSTS is off, as it only makes sense behind HTTPS.
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            hsts: None,
+            content_security_policy: Some("default-src 'self'".to_string()),
+            nosniff: true,
+        }
+    }
+
+    /// Sends `Strict-Transport-Security`, so browsers use HTTPS for `max_age`.
+    pub fn with_hsts(mut self, max_age: Duration) -> SecurityHeaders {
+        self.hsts = Some(max_age);
+        self
+    }
+
+    /// Replaces the `Content-Security-Policy`; `None` sends none.
+    pub fn with_content_security_policy(mut self, policy: Option<&str>) -> SecurityHeaders {
+        self.content_security_policy = policy.map(str::to_string);
+        self
+    }
+
+    pub fn with_nosniff(mut self, nosniff: bool) -> SecurityHeaders {
+        self.nosniff = nosniff;
+        self
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn after(&self, _request: &Request, mut response: Response) -> Response {
+        let mut add = |name: &str, value: String| {
+            if !response.headers.contains(name) {
+                response.headers.set(name, value);
+            }
+        };
+
+        if let Some(max_age) = self.hsts {
+            add(
+                "Strict-Transport-Security",
+                format!("max-age={}; includeSubDomains", max_age.as_secs()),
+            );
+        }
+        if let Some(policy) = &self.content_security_policy {
+            add("Content-Security-Policy", policy.clone());
+        }
+        if self.nosniff {
+            add("X-Content-Type-Options", "nosniff".to_string());
+        }
+        response
+    }
+}
+
+/// HTTP Basic authentication for everything under some path prefixes.
+///
+/// Passwords travel in the clear with every request, so use this behind HTTPS.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    realm: String,
+    prefixes: Vec<String>,
+    users: HashMap<String, String>,
+}
+
+impl BasicAuth {
+    pub fn new(realm: impl Into<String>) -> BasicAuth {
+        BasicAuth {
+            realm: realm.into(),
+            prefixes: Vec::new(),
+            users: HashMap::new(),
+        }
+    }
+
+    pub fn with_user(mut self, name: impl Into<String>, password: impl Into<String>) -> BasicAuth {
+        self.users.insert(name.into(), password.into());
+        self
+    }
+
+    /// Requires a login for `prefix` and every path below it, e.g. `/admin` covers
+    /// `/admin` and `/admin/users` but not `/administrator`.
+    pub fn protect(mut self, prefix: impl Into<String>) -> BasicAuth {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    fn protects(&self, path: &str) -> bool {
+        // a path that can't be normalized is within no prefix, so it's refused
+        // here rather than risk it reaching what one covers
+        http::path_segments(path).is_none()
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| http::path_within(path, prefix))
+    }
+
+    fn check(&self, authorization: &str) -> bool {
+        let credentials = authorization
+            .trim()
+            .split_once(' ')
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Basic"))
+            .and_then(|(_, encoded)| base64_decode(encoded.trim()))
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let Some((user, password)) = credentials.as_deref().and_then(|c| c.split_once(':')) else {
+            return false;
+        };
+
+        match self.users.get(user) {
+            Some(expected) => constant_time_eq(expected.as_bytes(), password.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        if !self.protects(&request.path) {
+            return None;
+        }
+        if request
+            .header("Authorization")
+            .is_some_and(|a| self.check(a))
+        {
+            return None;
+        }
+
+        Some(Response::text(401, "Unauthorized\n").with_header(
+            "WWW-Authenticate",
+            format!(
+                "Basic realm=\"{}\", charset=\"UTF-8\"",
+                self.realm.replace('"', "")
+            ),
+        ))
+    }
+}
+
+// compares without returning early, so the time taken doesn't reveal how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(
+            Some(b"ferris:crab".to_vec()),
+            base64_decode("ZmVycmlzOmNyYWI=")
+        );
+        assert_eq!(Some(b"ab".to_vec()), base64_decode("YWI="));
+        assert_eq!(None, base64_decode("not base64!"));
+    }
+
+    #[test]
+    fn protects_whole_path_segments() {
+        let auth = BasicAuth::new("admin").protect("/admin/");
+
+        assert!(auth.protects("/admin"));
+        assert!(auth.protects("/admin/users"));
+        assert!(auth.protects("/%61dmin/users"));
+        assert!(!auth.protects("/administrator"));
+        assert!(!auth.protects("/"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Reading the server's [`Config`] from a file, and noticing when it should be
+//! read again: on `SIGHUP`, or when the file changes.
+//!
+//! The file is JSON; every key is optional and missing ones keep their defaults:
+//!
+//! ```json
+//! {
+//!   "workers": 8,
+//!   "read_timeout": 30,
+//!   "keep_alive": 5,
+//!   "max_body_size": 1048576,
+//!   "upload_dir": "uploads",
+//!   "admin": "127.0.0.1:7879",
+//!   "error_pages": { "404": "errors/404.html" },
+//!   "hosts": [
+//!     {
+//!       "names": ["example.com", "www.example.com"],
+//!       "root": "sites/example",
+//!       "listings": ["/downloads"],
+//!       "dotfiles": false,
+//!       "error_pages": { "404": "404.html" },
+//!       "log": "logs/example.log",
+//!       "default": true
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Timeouts are in seconds, `null` turning them off. Relative paths are resolved
+//! against the directory the file is in, except a host's `error_pages`, which are
+//! resolved against its `root`. Unknown keys are rejected, so a typo doesn't go
+//! unnoticed.
+
+use crate::json::{self, JsonError, Value};
+use crate::server::Config;
+use crate::vhost::HostConfig;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read, or a host's log could not be opened.
+    Io(io::Error),
+    /// The file isn't valid JSON.
+    Json(JsonError),
+    /// The file is JSON, but not a valid config.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{e}"),
+            ConfigError::Json(e) => write!(f, "invalid JSON: {e}"),
+            ConfigError::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(e) => Some(e),
+            ConfigError::Json(e) => Some(e),
+            ConfigError::Invalid(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<JsonError> for ConfigError {
+    fn from(e: JsonError) -> ConfigError {
+        ConfigError::Json(e)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid(message.into())
+}
+
+/// Reads and validates the config file at `path`.
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    let text = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let config = parse(&text, base)?;
+    validate(&config)?;
+    Ok(config)
+}
+
+/// Parses a config file's contents, resolving relative paths against `base`.
+pub fn parse(text: &str, base: &Path) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+
+    for (key, value) in members(&json::parse(text)?, "the config")? {
+        match key.as_str() {
+            "workers" => config.workers = count(value, key)?,
+            "read_timeout" => config.read_timeout = seconds(value, key)?,
+            "keep_alive" => config.keep_alive = seconds(value, key)?,
+            "max_body_size" => config.body.max_size = count(value, key)? as u64,
+            "upload_dir" => config.body.upload_dir = base.join(string(value, key)?),
+            "admin" => {
+                config.admin = match value {
+                    Value::Null => None,
+                    _ => Some(string(value, key)?.parse().map_err(invalid)?),
+                }
+            }
+            "error_pages" => config.error_pages = error_pages(value, Some(base))?,
+            "hosts" => {
+                config.hosts = array(value, key)?
+                    .iter()
+                    .map(|host| parse_host(host, base))
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err(invalid(format!("unknown key {key}"))),
+        }
+    }
+
+    Ok(config)
+}
+
+fn parse_host(value: &Value, base: &Path) -> Result<HostConfig, ConfigError> {
+    let mut host = HostConfig::default();
+
+    for (key, value) in members(value, "a host")? {
+        match key.as_str() {
+            "names" => host.names = strings(value, key)?,
+            "root" => host.root = Some(base.join(string(value, key)?)),
+            // resolved against the root when the page is needed
+            "error_pages" => host.error_pages = error_pages(value, None)?,
+            "listings" => host.listings = strings(value, key)?,
+            "dotfiles" => {
+                host.dotfiles = value
+                    .as_bool()
+                    .ok_or_else(|| invalid("dotfiles must be true or false"))?
+            }
+            "log" => host.log = Some(base.join(string(value, key)?)),
+            "default" => {
+                host.default = value
+                    .as_bool()
+                    .ok_or_else(|| invalid("default must be true or false"))?
+            }
+            _ => return Err(invalid(format!("unknown host key {key}"))),
+        }
+    }
+
+    Ok(host)
+}
+
+/// Checks what parsing can't: that there are workers and that document roots exist.
+pub fn validate(config: &Config) -> Result<(), ConfigError> {
+    if config.workers == 0 {
+        return Err(invalid("workers must be at least 1"));
+    }
+    for root in config.hosts.iter().filter_map(|host| host.root.as_ref()) {
+        if !root.is_dir() {
+            return Err(invalid(format!("{} is not a directory", root.display())));
+        }
+    }
+    Ok(())
+}
+
+fn members<'a>(value: &'a Value, what: &str) -> Result<&'a [(String, Value)], ConfigError> {
+    match value {
+        Value::Object(members) => Ok(members),
+        _ => Err(invalid(format!("{what} must be an object"))),
+    }
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> Result<&'a [Value], ConfigError> {
+    value
+        .as_array()
+        .ok_or_else(|| invalid(format!("{key} must be an array")))
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> Result<&'a str, ConfigError> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(format!("{key} must be a string")))
+}
+
+fn strings(value: &Value, key: &str) -> Result<Vec<String>, ConfigError> {
+    array(value, key)?
+        .iter()
+        .map(|item| string(item, key).map(str::to_string))
+        .collect()
+}
+
+fn count(value: &Value, key: &str) -> Result<usize, ConfigError> {
+    match value.as_f64() {
+        Some(n) if n >= 0.0 && n.fract() == 0.0 && n <= usize::MAX as f64 => Ok(n as usize),
+        _ => Err(invalid(format!("{key} must be a whole number"))),
+    }
+}
+
+fn seconds(value: &Value, key: &str) -> Result<Option<Duration>, ConfigError> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    value
+        .as_f64()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .map(Some)
+        .ok_or_else(|| invalid(format!("{key} must be a number of seconds or null")))
+}
+
+fn error_pages(value: &Value, base: Option<&Path>) -> Result<HashMap<u16, PathBuf>, ConfigError> {
+    members(value, "error_pages")?
+        .iter()
+        .map(|(status, page)| {
+            let status = status
+                .parse()
+                .ok()
+                .filter(|status| (400..600).contains(status))
+                .ok_or_else(|| invalid(format!("{status} is not an error status")))?;
+            let page = Path::new(string(page, "an error page")?);
+            let page = match base {
+                Some(base) => base.join(page),
+                None => page.to_path_buf(),
+            };
+            Ok((status, page))
+        })
+        .collect()
+}
+
+// bumped by the signal handler; each watcher remembers the count it last saw
+static HANGUPS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+mod signal {
+    use super::HANGUPS;
+    use std::sync::atomic::Ordering;
+    use std::sync::Once;
+
+    const SIGHUP: i32 = 1;
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    // only async-signal-safe work is allowed here; an atomic add is
+    extern "C" fn on_hangup(_: i32) {
+        HANGUPS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn install() {
+        static INSTALLED: Once = Once::new();
+        INSTALLED.call_once(|| {
+            // SAFETY: the handler only touches an atomic
+            if unsafe { signal(SIGHUP, on_hangup) } == SIG_ERR {
+                eprintln!("Failed to install a SIGHUP handler");
+            }
+        });
+    }
+}
+
+/// Tells when a config file should be read again.
+pub(crate) struct Watcher {
+    path: PathBuf,
+    watch: bool,
+    hangups: usize,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    /// Watches for `SIGHUP`, which from now on no longer ends the process, and
+    /// with `watch` for changes to the file at `path`.
+    pub(crate) fn new(path: PathBuf, watch: bool) -> Watcher {
+        #[cfg(unix)]
+        signal::install();
+
+        let modified = modified(&path);
+        Watcher {
+            path,
+            watch,
+            hangups: HANGUPS.load(Ordering::SeqCst),
+            modified,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether a `SIGHUP` arrived or the file changed since the last call.
+    pub(crate) fn changed(&mut self) -> bool {
+        let hangups = HANGUPS.load(Ordering::SeqCst);
+        let hung_up = hangups != self.hangups;
+        self.hangups = hangups;
+
+        let mut modified = false;
+        if self.watch {
+            let now = self::modified(&self.path);
+            // a file that is gone for a moment, e.g. while an editor saves it, isn't a change
+            if now.is_some() && now != self.modified {
+                self.modified = now;
+                modified = true;
+            }
+        }
+
+        hung_up || modified
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_config_file() {
+        let text = r#"{
+            "workers": 2,
+            "keep_alive": null,
+            "read_timeout": 1.5,
+            "error_pages": { "500": "500.html" },
+            "hosts": [{ "names": ["a.test"], "root": "a", "error_pages": { "404": "404.html" }, "dotfiles": true }]
+        }"#;
+        let config = parse(text, Path::new("/srv")).unwrap();
+
+        assert_eq!(2, config.workers);
+        assert_eq!(None, config.keep_alive);
+        assert_eq!(Some(Duration::from_millis(1500)), config.read_timeout);
+        assert_eq!(
+            Some(&PathBuf::from("/srv/500.html")),
+            config.error_pages.get(&500)
+        );
+        assert_eq!(vec!["a.test".to_string()], config.hosts[0].names);
+        assert_eq!(Some(PathBuf::from("/srv/a")), config.hosts[0].root);
+        assert!(config.hosts[0].dotfiles);
+        assert_eq!(
+            Some(&PathBuf::from("404.html")),
+            config.hosts[0].error_pages.get(&404)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        let base = Path::new("");
+
+        assert!(matches!(parse("{", base), Err(ConfigError::Json(_))));
+        assert!(matches!(
+            parse(r#"{"wokers": 2}"#, base),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(parse(r#"{"workers": -1}"#, base).is_err());
+        assert!(parse(r#"{"error_pages": {"200": "ok.html"}}"#, base).is_err());
+
+        let config = parse(r#"{"workers": 0}"#, base).unwrap();
+        assert!(validate(&config).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! The pages served by the `multithreaded_server` binary.
+
+use crate::error::ServerError;
+use crate::http::Response;
+use crate::json::Value;
+use crate::middleware::{RequestId, SecurityHeaders};
+use crate::server::Router;
+use crate::session::{MemoryStore, Sessions};
+use crate::sse::{Broadcaster, Event};
+use crate::template::render;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Builds the demo app; `/sleep` takes `sleep` to answer, simulating a slow request.
+///
+/// The app's state lives in `sessions` and `events`, so a router built again for
+/// a reloaded config carries on with the same logins and subscribers. `/events`
+/// streams what's published to `events`, which has to be closed for the server
+/// to finish shutting down while anyone is subscribed.
+pub fn router(sleep: Duration, sessions: Arc<MemoryStore>, events: Broadcaster) -> Router {
+    let publisher = events.clone();
+
+    Router::new()
+        .wrap(RequestId)
+        .wrap(SecurityHeaders::new())
+        .wrap(Sessions::new(sessions))
+        // try /?name=Ferris
+        .get("/", |request| {
+            render(
+                "hello.html",
+                &Value::object([("name", request.query_param("name").into())]),
+            )
+        })
+        .get("/sleep", move |_| {
+            thread::sleep(sleep);
+            render("hello.html", &Value::Null)
+        })
+        // describes whatever body was sent, handy for trying out forms and uploads
+        .post("/echo", |request| {
+            Response::json(200, &request.body.to_json())
+        })
+        // counts visits per browser; `curl -b jar -c jar localhost:7878/visits` to try it
+        .get("/visits", |request| {
+            let session = request.session.clone().unwrap_or_default();
+            let visits = session
+                .get("visits")
+                .and_then(|visits| visits.as_f64())
+                .unwrap_or(0.0)
+                + 1.0;
+            session.insert("visits", visits);
+            Response::text(200, format!("Visits: {visits}\n"))
+        })
+        // `curl -N localhost:7878/events` in one terminal, then
+        // `curl -d message=hi localhost:7878/events` in another
+        .get("/events", move |request| events.subscribe(request))
+        .post("/events", move |request| {
+            match request.body.field("message") {
+                Some(message) => {
+                    let id = publisher.publish(Event::new(message).event("message"));
+                    Response::json(202, &Value::object([("id", id.into())]))
+                }
+                None => Response::text(400, "Missing `message` field\n"),
+            }
+        })
+        .fallback(|_| {
+            // without a usable 404.html, the server's built-in page is sent
+            render("404.html", &Value::Null)
+                .map(|response| Response {
+                    status: 404,
+                    ..response
+                })
+                .map_err(|e| {
+                    eprintln!("Template error: {e}");
+                    ServerError::NotFound
+                })
+        })
+}
@@ -0,0 +1,713 @@
+//! The accept loop, routing and per-connection handling.
+
+use crate::admin;
+use crate::body::BodyConfig;
+use crate::error::{ErrorPages, ServerError};
+use crate::hello::{self, ThreadPool};
+use crate::http::{ParseError, Request, Response};
+use crate::listener::{self, Connection, Endpoint, Listener};
+use crate::middleware::Middleware;
+use crate::reload::{self, ConfigError, Watcher};
+use crate::vhost::{HostConfig, Hosts};
+use std::collections::HashMap;
+use std::io::{self, prelude::*, BufReader};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how much of a rejected request is read and discarded before closing
+const MAX_DRAIN: u64 = 64 * 1024;
+// how often an idle kept-alive connection checks whether the server is shutting down
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of threads in the pool.
+    pub workers: usize,
+    pub body: BodyConfig,
+    /// How long to wait on a silent client before giving up on it.
+    pub read_timeout: Option<Duration>,
+    /// How long a connection is kept open for another request after a response;
+    /// `None`, the default, closes every connection after one request. An open
+    /// connection keeps its worker busy, so keep this short.
+    pub keep_alive: Option<Duration>,
+    /// The sites to serve; when empty, every request goes to one catch-all host.
+    pub hosts: Vec<HostConfig>,
+    /// Where to serve the admin API (see [`crate::admin`]); off by default. Only
+    /// read when the server starts, not on a reload.
+    pub admin: Option<Endpoint>,
+    /// Pages for errors, by status, used where a host has none of its own and for
+    /// requests rejected before a host was picked. Missing pages fall back to built-in ones.
+    pub error_pages: HashMap<u16, PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            workers: 4,
+            body: BodyConfig::default(),
+            read_timeout: Some(Duration::from_secs(30)),
+            keep_alive: None,
+            hosts: Vec::new(),
+            admin: None,
+            error_pages: HashMap::new(),
+        }
+    }
+}
+
+pub type Handler = dyn Fn(&Request) -> Result<Response, ServerError> + Send + Sync;
+
+/// What a handler may return: a [`Response`], or a `Result` whose error is answered
+/// with the matching error page.
+pub trait HandlerResult {
+    fn into_result(self) -> Result<Response, ServerError>;
+}
+
+impl HandlerResult for Response {
+    fn into_result(self) -> Result<Response, ServerError> {
+        Ok(self)
+    }
+}
+
+impl<E: Into<ServerError>> HandlerResult for Result<Response, E> {
+    fn into_result(self) -> Result<Response, ServerError> {
+        self.map_err(Into::into)
+    }
+}
+
+/// Maps a method and an exact path to a handler.
+pub struct Router {
+    routes: Vec<(String, String, Box<Handler>)>,
+    fallback: Box<Handler>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            fallback: Box::new(|_| Err(ServerError::NotFound)),
+            middleware: Vec::new(),
+        }
+    }
+
+    pub fn route<F, R>(mut self, method: &str, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+    {
+        self.routes.push((
+            method.to_string(),
+            path.to_string(),
+            Box::new(move |request| handler(request).into_result()),
+        ));
+        self
+    }
+
+    pub fn get<F, R>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+    {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<F, R>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+    {
+        self.route("POST", path, handler)
+    }
+
+    /// Answers requests no route matched; [`ServerError::NotFound`] by default.
+    pub fn fallback<F, R>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: HandlerResult,
+    {
+        self.fallback = Box::new(move |request| handler(request).into_result());
+        self
+    }
+
+    /// Runs `middleware` around every request this router answers, including ones
+    /// served from the document root or by the fallback. The first added runs outermost.
+    pub fn wrap(mut self, middleware: impl Middleware + 'static) -> Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn middleware(&self) -> &[Box<dyn Middleware>] {
+        &self.middleware
+    }
+
+    pub fn handle(&self, request: &Request) -> Result<Response, ServerError> {
+        self.dispatch(request)
+            .unwrap_or_else(|| self.handle_unmatched(request))
+    }
+
+    /// Runs the matching route, or returns `None` if no route has this path.
+    pub fn dispatch(&self, request: &Request) -> Option<Result<Response, ServerError>> {
+        let mut allowed = Vec::new();
+
+        for (method, path, handler) in &self.routes {
+            if *path != request.path {
+                continue;
+            }
+            if *method == request.method {
+                return Some(handler(request));
+            }
+            allowed.push(method.clone());
+        }
+
+        // the path exists, just not for this method
+        if !allowed.is_empty() {
+            return Some(Err(ServerError::MethodNotAllowed(allowed)));
+        }
+
+        None
+    }
+
+    /// Runs the fallback handler.
+    pub fn handle_unmatched(&self, request: &Request) -> Result<Response, ServerError> {
+        (self.fallback)(request)
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+// what requests are answered with; a reload swaps in a new one, while requests
+// already being answered hold on to the one they started with
+struct Site {
+    config: Config,
+    hosts: Hosts,
+}
+
+type RouterFor = dyn FnMut(&HostConfig) -> Arc<Router> + Send;
+type Reload = (Config, mpsc::Sender<Result<(), ConfigError>>);
+type ShutdownHook = Box<dyn FnOnce() + Send>;
+
+/// Serves requests from any number of listeners on one shared [`ThreadPool`].
+pub struct Server {
+    listeners: Vec<Listener>,
+    endpoints: Vec<Endpoint>,
+    admin: Option<(Listener, Endpoint)>,
+    pool: ThreadPool,
+    site: Arc<RwLock<Arc<Site>>>,
+    // kept to build the hosts of a reloaded config
+    router_for: Mutex<Box<RouterFor>>,
+    reloads: mpsc::Sender<Reload>,
+    pending_reloads: Mutex<mpsc::Receiver<Reload>>,
+    watcher: Option<Watcher>,
+    shutdown: Arc<AtomicBool>,
+    // a mutex only so the server can be shared with the accept threads
+    shutdown_hooks: Mutex<Vec<ShutdownHook>>,
+}
+
+impl Server {
+    /// Binds to `addr`, with every host in `config` sharing `router`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, config: Config, router: Router) -> io::Result<Server> {
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+        let router = Arc::new(router);
+
+        Server::new(
+            vec![listener],
+            config,
+            Box::new(move |_| Arc::clone(&router)),
+        )
+    }
+
+    /// Binds to `addr`, giving each host in `config` the routes `router_for` builds for it.
+    pub fn bind_hosts<A, F>(addr: A, config: Config, router_for: F) -> io::Result<Server>
+    where
+        A: ToSocketAddrs,
+        F: FnMut(&HostConfig) -> Router + Send + 'static,
+    {
+        let listener = Listener::Tcp(TcpListener::bind(addr)?);
+
+        Server::listen(vec![listener], config, router_for)
+    }
+
+    /// Serves on all of `listeners` at once, e.g. IPv4, IPv6 and a Unix socket.
+    ///
+    /// `router_for` is asked again for each host whenever the config is reloaded.
+    pub fn listen<F>(
+        listeners: Vec<Listener>,
+        config: Config,
+        mut router_for: F,
+    ) -> io::Result<Server>
+    where
+        F: FnMut(&HostConfig) -> Router + Send + 'static,
+    {
+        Server::new(
+            listeners,
+            config,
+            Box::new(move |host| Arc::new(router_for(host))),
+        )
+    }
+
+    fn new(
+        listeners: Vec<Listener>,
+        config: Config,
+        mut router_for: Box<RouterFor>,
+    ) -> io::Result<Server> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "nothing to listen on",
+            ));
+        }
+        let endpoints = listeners
+            .iter()
+            .map(Listener::local_endpoint)
+            .collect::<io::Result<_>>()?;
+        let admin = match &config.admin {
+            Some(endpoint) => {
+                let listener = Listener::bind(endpoint)?;
+                let endpoint = listener.local_endpoint()?;
+                Some((listener, endpoint))
+            }
+            None => None,
+        };
+        let hosts = Hosts::build(&config.hosts, &mut router_for)?;
+        let (sender, receiver) = mpsc::channel();
+
+        Ok(Server {
+            listeners,
+            endpoints,
+            admin,
+            pool: ThreadPool::new(config.workers),
+            site: Arc::new(RwLock::new(Arc::new(Site { config, hosts }))),
+            router_for: Mutex::new(router_for),
+            reloads: sender,
+            pending_reloads: Mutex::new(receiver),
+            watcher: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            shutdown_hooks: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Reloads the config from the file at `path` (see [`crate::reload`] for its
+    /// format) whenever the process gets `SIGHUP`, and with `watch` also whenever
+    /// the file changes. A file that fails to load or validate is reported and the
+    /// old config stays in place.
+    pub fn with_config_file(mut self, path: impl Into<PathBuf>, watch: bool) -> Server {
+        self.watcher = Some(Watcher::new(path.into(), watch));
+        self
+    }
+
+    /// Runs `hook` once the server stops accepting connections, before it waits for
+    /// the requests still being answered, e.g. to end the streams of a
+    /// [`Broadcaster`](crate::sse::Broadcaster) that would otherwise never finish.
+    pub fn with_shutdown_hook(mut self, hook: impl FnOnce() + Send + 'static) -> Server {
+        self.shutdown_hooks
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Box::new(hook));
+        self
+    }
+
+    /// The address of the first TCP listener, useful after binding to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoints
+            .iter()
+            .find_map(|endpoint| match endpoint {
+                Endpoint::Tcp(addr) => Some(*addr),
+                #[cfg(unix)]
+                _ => None,
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no TCP listener"))
+    }
+
+    /// Everything the server listens on.
+    pub fn local_endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Where the admin API is served, if it is.
+    pub fn admin_endpoint(&self) -> Option<&Endpoint> {
+        self.admin.as_ref().map(|(_, endpoint)| endpoint)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            endpoints: self.endpoints.clone(),
+            flag: Arc::clone(&self.shutdown),
+        }
+    }
+
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            sender: self.reloads.clone(),
+        }
+    }
+
+    /// Serves until a [`ShutdownHandle`] is used.
+    pub fn run(self) {
+        self.serve(None);
+    }
+
+    /// Serves `connections` connections and then shuts down.
+    pub fn run_for(self, connections: usize) {
+        self.serve(Some(connections));
+    }
+
+    fn serve(mut self, limit: Option<usize>) {
+        for endpoint in &self.endpoints {
+            println!("Listening on {endpoint}");
+        }
+
+        let accepted = AtomicUsize::new(0);
+        let handle = self.shutdown_handle();
+        let watcher = self.watcher.take();
+        let shutdown_hooks = self
+            .shutdown_hooks
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner());
+        let shutdown_hooks = std::mem::take(shutdown_hooks);
+
+        // the admin API runs on its own thread until the pool has drained, so
+        // readiness can be watched going down during shutdown
+        let drained = Arc::new(AtomicBool::new(false));
+        let admin = self.admin.take().map(|(listener, endpoint)| {
+            println!("Admin API on {endpoint}");
+            let router = admin::router(self.pool.monitor(), Arc::clone(&self.shutdown));
+            let drained = Arc::clone(&drained);
+            let thread = thread::spawn(move || admin::serve(&listener, &router, &drained));
+            (thread, endpoint)
+        });
+
+        // accept() blocks, so each listener gets its own thread; they all hand
+        // connections to the same pool and end together once the shutdown flag is set
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                let (server, accepted, handle) = (&self, &accepted, &handle);
+                scope.spawn(move || server.accept_loop(listener, limit, accepted, handle));
+            }
+            let server = &self;
+            scope.spawn(move || server.reload_loop(watcher));
+        });
+
+        println!("Shutting down.");
+        for hook in shutdown_hooks {
+            hook();
+        }
+
+        // dropping the pool waits for the jobs that are still running
+        drop(self.pool);
+
+        drained.store(true, Ordering::SeqCst);
+        if let Some((thread, endpoint)) = admin {
+            listener::poke(&endpoint);
+            let _ = thread.join();
+        }
+    }
+
+    fn accept_loop(
+        &self,
+        listener: &Listener,
+        limit: Option<usize>,
+        accepted: &AtomicUsize,
+        handle: &ShutdownHandle,
+    ) {
+        loop {
+            let result = listener.accept();
+
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let (connection, peer) = match result {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Failed to accept a connection: {e}");
+                    thread::sleep(listener::ACCEPT_BACKOFF);
+                    continue;
+                }
+            }; // connection is dropped at the end of the job
+
+            let read_timeout = current_site(&self.site).config.read_timeout;
+            if let Err(e) = connection.set_read_timeout(read_timeout) {
+                eprintln!("Failed to set a read timeout: {e}");
+            }
+
+            let site = Arc::clone(&self.site);
+            let shutdown = Arc::clone(&self.shutdown);
+
+            self.pool.execute(move || {
+                handle_connection(connection, peer, &site, &shutdown);
+            });
+
+            if limit == Some(accepted.fetch_add(1, Ordering::SeqCst) + 1) {
+                handle.shutdown();
+                break;
+            }
+        }
+    }
+
+    // applies reloads sent through a ReloadHandle or noticed by the watcher, until
+    // the server shuts down
+    fn reload_loop(&self, mut watcher: Option<Watcher>) {
+        let reloads = self
+            .pending_reloads
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        while !self.shutdown.load(Ordering::SeqCst) {
+            match reloads.recv_timeout(IDLE_POLL) {
+                Ok((config, reply)) => {
+                    let _ = reply.send(self.apply(config));
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+
+            let Some(watcher) = watcher.as_mut() else {
+                continue;
+            };
+            if !watcher.changed() {
+                continue;
+            }
+            let path = watcher.path().display();
+            match reload::load(watcher.path()).and_then(|config| self.apply(config)) {
+                Ok(()) => println!("Reloaded the configuration from {path}."),
+                Err(e) => eprintln!("Keeping the old configuration, {path} is invalid: {e}"),
+            }
+        }
+    }
+
+    fn apply(&self, config: Config) -> Result<(), ConfigError> {
+        reload::validate(&config)?;
+        let hosts = {
+            let mut router_for = self.router_for.lock().unwrap_or_else(|e| e.into_inner());
+            Hosts::build(&config.hosts, &mut *router_for)?
+        };
+
+        self.pool.resize(config.workers);
+        *self.site.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(Site { config, hosts });
+        Ok(())
+    }
+}
+
+fn current_site(site: &RwLock<Arc<Site>>) -> Arc<Site> {
+    Arc::clone(&site.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Stops a running [`Server`] from another thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    endpoints: Vec<Endpoint>,
+    flag: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting connections; requests already being handled still finish.
+    pub fn shutdown(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+
+        // connect once to every listener to wake its accept loop up and let it see the flag
+        for endpoint in &self.endpoints {
+            listener::poke(endpoint);
+        }
+    }
+}
+
+/// Swaps the config of a running [`Server`] from another thread.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    sender: mpsc::Sender<Reload>,
+}
+
+impl ReloadHandle {
+    /// Validates `config` and, if it is fine, answers requests that arrive from now
+    /// on with it and resizes the pool to its worker count. Requests already being
+    /// answered finish with the old config. Listeners and the admin API stay as
+    /// they are.
+    ///
+    /// Waits for the server to apply it, so only call this while the server runs.
+    pub fn reload(&self, config: Config) -> Result<(), ConfigError> {
+        let stopped = || {
+            ConfigError::Io(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the server has stopped",
+            ))
+        };
+
+        let (reply, result) = mpsc::channel();
+        self.sender.send((config, reply)).map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+// answers the requests sent on `connection`, each by the host it is for and with
+// the config current when it arrived, for as long as both sides want to keep the
+// connection open; nothing a client sends can make this panic, failures are
+// answered with an error page where possible and logged
+fn handle_connection(
+    connection: Connection,
+    peer: Option<SocketAddr>,
+    site: &RwLock<Arc<Site>>,
+    shutdown: &AtomicBool,
+) {
+    let mut reader = BufReader::new(connection);
+
+    loop {
+        let site = current_site(site);
+        let (hosts, config) = (&site.hosts, &site.config);
+        match serve_request(&mut reader, peer, hosts, config, shutdown) {
+            Ok(true) => {}
+            // the client hung up or its connection broke; nobody left to tell
+            Ok(false) | Err(ServerError::Parse(ParseError::ConnectionClosed)) => return,
+            Err(e) => {
+                eprintln!("Connection error: {e}");
+                return;
+            }
+        }
+
+        if !wait_for_request(&mut reader, config, shutdown) {
+            return;
+        }
+    }
+}
+
+// answers one request, returning whether the connection can be used for another
+fn serve_request<S: Read + Write>(
+    reader: &mut BufReader<S>,
+    peer: Option<SocketAddr>,
+    hosts: &Hosts,
+    config: &Config,
+    shutdown: &AtomicBool,
+) -> Result<bool, ServerError> {
+    let pages = ErrorPages::new(&config.error_pages, None);
+
+    let request = Request::read_from(reader, &config.body).map(|mut request| {
+        request.peer = peer;
+        request
+    });
+    let head = request
+        .as_ref()
+        .is_ok_and(|request| request.method == "HEAD");
+    let (mut response, rejected, client_keeps_alive) = match request {
+        Ok(mut request) => {
+            hello::describe_job(format!("{} {}", request.method, request.path));
+            let keeps_alive = wants_keep_alive(&request);
+            match hosts.select(&request) {
+                Some(host) => (host.handle(&mut request, pages), false, keeps_alive),
+                None => {
+                    let e = ServerError::Parse(ParseError::Malformed("missing Host header"));
+                    (e.to_response(&[pages]), true, false)
+                }
+            }
+        }
+        Err(e) => {
+            let e = ServerError::from(e);
+            if e.status().is_none() {
+                return Err(e);
+            }
+            // a silent client has nothing left to drain
+            let rejected = !matches!(e, ServerError::Timeout);
+            (e.to_response(&[pages]), rejected, false)
+        }
+    };
+
+    // a streamed body ends when the connection does, so it can't be kept
+    let keep_alive = client_keeps_alive
+        && config.keep_alive.is_some()
+        && response.stream.is_none()
+        && !shutdown.load(Ordering::SeqCst);
+    if keep_alive {
+        response.headers.set("Connection", "keep-alive");
+    } else if response.stream.is_none() {
+        response.headers.set("Connection", "close");
+    }
+
+    // the client may already be gone; that's no reason to take the worker down
+    let written = if head {
+        response.write_head_to(reader.get_mut())
+    } else {
+        response.write_to(reader.get_mut())
+    };
+    written.map_err(ServerError::Io)?;
+
+    // closing with unread input makes the OS reset the connection, which can destroy
+    // the error response before the client reads it, so swallow a little of what's left
+    if rejected {
+        let _ = io::copy(&mut reader.take(MAX_DRAIN), &mut io::sink());
+    }
+
+    Ok(keep_alive)
+}
+
+// HTTP/1.1 keeps connections open unless told otherwise, HTTP/1.0 only when asked to
+fn wants_keep_alive(request: &Request) -> bool {
+    let has = |token: &str| {
+        request
+            .headers
+            .get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+
+    match request.version.as_str() {
+        "HTTP/1.1" => !has("close"),
+        _ => has("keep-alive"),
+    }
+}
+
+// waits for the next request on a kept-alive connection; false if the client stays
+// quiet for longer than `config.keep_alive`, hangs up, or the server is shutting down
+fn wait_for_request(
+    reader: &mut BufReader<Connection>,
+    config: &Config,
+    shutdown: &AtomicBool,
+) -> bool {
+    let Some(keep_alive) = config.keep_alive else {
+        return false;
+    };
+    // the client may have sent its next request along with the last one
+    if !reader.buffer().is_empty() {
+        return true;
+    }
+
+    hello::describe_job("waiting on a kept-alive connection");
+    let deadline = Instant::now() + keep_alive;
+    let arrived = loop {
+        // short waits, so an idle connection doesn't hold up shutdown
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || shutdown.load(Ordering::SeqCst) {
+            break false;
+        }
+        if reader
+            .get_ref()
+            .set_read_timeout(Some(left.min(IDLE_POLL)))
+            .is_err()
+        {
+            break false;
+        }
+
+        match reader.fill_buf() {
+            Ok(buffer) => break !buffer.is_empty(),
+            Err(e) if is_wait_over(&e) => continue,
+            Err(_) => break false,
+        }
+    };
+
+    arrived
+        && reader
+            .get_ref()
+            .set_read_timeout(config.read_timeout)
+            .is_ok()
+}
+
+fn is_wait_over(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
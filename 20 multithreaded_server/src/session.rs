@@ -0,0 +1,425 @@
+//! Server-side sessions: a random id in a cookie, with the data kept in a store.
+
+use crate::cookie::{Cookie, SameSite};
+use crate::http::{Request, Response};
+use crate::json::{self, Value};
+use crate::middleware::Middleware;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 16 random bytes, hex encoded
+const ID_LEN: usize = 32;
+
+pub type Values = BTreeMap<String, Value>;
+
+/// Where session data lives between requests.
+pub trait SessionStore: Send + Sync {
+    /// The data of session `id`, or `None` if there is none or it has expired.
+    fn load(&self, id: &str) -> io::Result<Option<Values>>;
+    fn save(&self, id: &str, values: &Values, expires: SystemTime) -> io::Result<()>;
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+/// Keeps sessions in memory; they are lost when the server stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SystemTime, Values)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// so one store can be shared, e.g. by the routers built again on every reload
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    fn load(&self, id: &str) -> io::Result<Option<Values>> {
+        (**self).load(id)
+    }
+
+    fn save(&self, id: &str, values: &Values, expires: SystemTime) -> io::Result<()> {
+        (**self).save(id, values, expires)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        (**self).remove(id)
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<Values>> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now();
+
+        match sessions.get(id) {
+            Some((expires, values)) if *expires > now => Ok(Some(values.clone())),
+            Some(_) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, values: &Values, expires: SystemTime) -> io::Result<()> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+
+        // expired sessions nobody comes back for would otherwise pile up
+        let now = SystemTime::now();
+        sessions.retain(|_, (expires, _)| *expires > now);
+
+        sessions.insert(id.to_string(), (expires, values.clone()));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        Ok(())
+    }
+}
+
+/// Keeps each session in a JSON file in a directory, so sessions survive restarts.
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Creates `dir` if needed and clears out sessions that expired while the
+    /// server was down.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<FileStore> {
+        let store = FileStore { dir: dir.into() };
+        fs::create_dir_all(&store.dir)?;
+        store.remove_expired()?;
+        Ok(store)
+    }
+
+    pub fn remove_expired(&self) -> io::Result<()> {
+        let now = SystemTime::now();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") && read_file(&path, now).is_none() {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+// the session's values, or `None` if the file is unreadable, corrupt or expired
+fn read_file(path: &Path, now: SystemTime) -> Option<Values> {
+    let contents = fs::read_to_string(path).ok()?;
+    let stored = json::parse(&contents).ok()?;
+
+    let expires = UNIX_EPOCH + Duration::from_secs_f64(stored.get("expires")?.as_f64()?);
+    if expires <= now {
+        return None;
+    }
+
+    match stored.get("values")? {
+        Value::Object(members) => Some(members.iter().cloned().collect()),
+        _ => None,
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<Values>> {
+        let path = self.path(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let values = read_file(&path, SystemTime::now());
+        if values.is_none() {
+            fs::remove_file(&path)?;
+        }
+        Ok(values)
+    }
+
+    fn save(&self, id: &str, values: &Values, expires: SystemTime) -> io::Result<()> {
+        let expires = expires.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let stored = Value::object([
+            ("expires", Value::Number(expires.as_secs() as f64)),
+            ("values", Value::object(values.clone())),
+        ]);
+
+        // written aside and renamed into place, so a crash never leaves half a file
+        let path = self.path(id);
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, stored.to_string())?;
+        fs::rename(&temp, &path)
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The session of the current request; clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    id: Option<String>,
+    values: Values,
+    changed: bool,
+    destroyed: bool,
+    // the id this session had before `renew`, to be removed from the store
+    old_id: Option<String>,
+}
+
+impl Session {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The session id, or `None` for a new session nothing has been stored in yet.
+    pub fn id(&self) -> Option<String> {
+        self.state().id.clone()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.state().values.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: impl Into<String>, value: impl Into<Value>) {
+        let mut state = self.state();
+        state.values.insert(key.into(), value.into());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        let mut state = self.state();
+        state.changed = true;
+        state.values.remove(key)
+    }
+
+    /// Gives the session a new id, keeping its data. Call this on login so an id
+    /// planted on the client beforehand is worthless.
+    pub fn renew(&self) {
+        let mut state = self.state();
+        if state.old_id.is_none() {
+            state.old_id = state.id.take();
+        }
+        state.id = None;
+        state.changed = true;
+    }
+
+    /// Deletes the session and its cookie, e.g. on logout.
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.values.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Loads the session named by the request's cookie before the handler runs, and
+/// stores it and refreshes the cookie afterwards, as [`Middleware`] on a router.
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+}
+
+impl Sessions {
+    /// Sessions named by a `session` cookie, expiring after 30 minutes without a request.
+    pub fn new(store: impl SessionStore + 'static) -> Sessions {
+        Sessions {
+            store: Box::new(store),
+            cookie_name: "session".to_string(),
+            ttl: Duration::from_secs(30 * 60),
+            secure: false,
+        }
+    }
+
+    pub fn with_cookie_name(mut self, name: impl Into<String>) -> Sessions {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// How long a session lives after the last request that used it.
+    pub fn with_ttl(mut self, ttl: Duration) -> Sessions {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Marks the cookie `Secure`; turn on when serving over HTTPS.
+    pub fn with_secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    /// The request's session, or a new empty one.
+    pub fn load(&self, request: &Request) -> Session {
+        let id = request
+            .cookie(&self.cookie_name)
+            // anything else can't be one of ours, and mustn't reach the store as a file name
+            .filter(|id| id.len() == ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit()));
+
+        let loaded = id.and_then(|id| match self.store.load(&id) {
+            Ok(values) => Some((id, values?)),
+            Err(e) => {
+                eprintln!("Failed to load session: {e}");
+                None
+            }
+        });
+
+        let state = match loaded {
+            Some((id, values)) => State {
+                id: Some(id),
+                values,
+                ..State::default()
+            },
+            None => State::default(),
+        };
+        Session {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Stores `session` and sets or clears its cookie on `response`.
+    pub fn save(&self, session: &Session, response: Response) -> Response {
+        let mut state = session.state();
+
+        let mut stale = Vec::new();
+        stale.extend(state.old_id.take());
+        if state.destroyed {
+            stale.extend(state.id.take());
+        }
+        for id in stale {
+            if let Err(e) = self.store.remove(&id) {
+                eprintln!("Failed to remove session: {e}");
+            }
+        }
+        if state.destroyed {
+            return response.with_cookie(&self.cookie(Cookie::removal(&self.cookie_name)));
+        }
+
+        // a new session is only stored once there is something in it
+        if state.id.is_none() {
+            if !state.changed || state.values.is_empty() {
+                return response;
+            }
+            state.id = Some(random_id());
+        }
+        let id = state.id.clone().unwrap_or_default();
+
+        // every request pushes the expiry back
+        let expires = SystemTime::now() + self.ttl;
+        if let Err(e) = self.store.save(&id, &state.values, expires) {
+            eprintln!("Failed to save session: {e}");
+            return response;
+        }
+        state.changed = false;
+
+        response
+            .with_cookie(&self.cookie(Cookie::new(&self.cookie_name, id).with_max_age(self.ttl)))
+    }
+
+    fn cookie(&self, cookie: Cookie) -> Cookie {
+        cookie
+            .with_path("/")
+            .with_http_only(true)
+            .with_secure(self.secure)
+            .with_same_site(SameSite::Lax)
+    }
+}
+
+impl Middleware for Sessions {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        request.session = Some(self.load(request));
+        None
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        match &request.session {
+            Some(session) => self.save(session, response),
+            None => response,
+        }
+    }
+}
+
+// 128 bits from the OS's generator where there is one; otherwise from std's
+// randomly keyed hasher, which is seeded from the OS as well
+pub(crate) fn random_id() -> String {
+    let mut bytes = [0u8; ID_LEN / 2];
+
+    let from_os = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes));
+    if from_os.is_err() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(nanos);
+            hasher.write_usize(i);
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+    }
+
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_store_forgets_expired_sessions() {
+        let store = MemoryStore::new();
+        let values = Values::from([("user".to_string(), Value::from("ferris"))]);
+
+        store
+            .save("live", &values, SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        store
+            .save("dead", &values, SystemTime::now() - Duration::from_secs(1))
+            .unwrap();
+
+        assert_eq!(Some(values), store.load("live").unwrap());
+        assert_eq!(None, store.load("dead").unwrap());
+        assert_eq!(1, store.len());
+    }
+
+    #[test]
+    fn ids_are_random_hex() {
+        let (a, b) = (random_id(), random_id());
+
+        assert_eq!(ID_LEN, a.len());
+        assert!(a.bytes().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
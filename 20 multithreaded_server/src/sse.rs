@@ -0,0 +1,252 @@
+//! Server-Sent Events (`text/event-stream`) responses fed by a publish API.
+//!
+//! Each connected client keeps a worker of the [`ThreadPool`](crate::hello::ThreadPool)
+//! busy for as long as it stays subscribed, so size the pool with that in mind.
+
+use crate::http::{Request, Response};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// A single event; `id` is filled in by [`Broadcaster::publish`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    pub id: Option<u64>,
+    pub event: Option<String>,
+    pub data: String,
+    /// Asks the client to wait this long before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the event type, dispatched to `addEventListener(name, …)` in the browser.
+    pub fn event(mut self, name: impl Into<String>) -> Event {
+        self.event = Some(name.into());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+// the wire format: one `field: value` line each, then a blank line
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(id) = self.id {
+            writeln!(f, "id: {id}")?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        // a newline would end the field early, so multi-line data takes several fields
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+        }
+        writeln!(f)
+    }
+}
+
+fn single_line(s: &str) -> &str {
+    s.split(['\r', '\n']).next().unwrap_or("")
+}
+
+/// Fans published events out to every subscribed connection.
+///
+/// Cloning is cheap and every clone publishes to the same subscribers, so
+/// other threads can hold one and push events into it.
+#[derive(Clone)]
+pub struct Broadcaster {
+    shared: Arc<Shared>,
+    heartbeat: Duration,
+    retry: Option<Duration>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    published: Condvar,
+}
+
+struct State {
+    next_id: u64,
+    // the most recent events, kept so reconnecting clients can catch up
+    replay: VecDeque<Event>,
+    capacity: usize,
+    closed: bool,
+}
+
+impl Broadcaster {
+    /// Keeps the last `replay_capacity` events for clients resuming with `Last-Event-ID`.
+    ///
+    /// Subscribers are fed from the same buffer, so one that falls more than
+    /// `replay_capacity` events behind skips the oldest.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if the capacity is zero.
+    pub fn new(replay_capacity: usize) -> Broadcaster {
+        assert!(replay_capacity > 0);
+
+        Broadcaster {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    next_id: 1,
+                    replay: VecDeque::with_capacity(replay_capacity),
+                    capacity: replay_capacity,
+                    closed: false,
+                }),
+                published: Condvar::new(),
+            }),
+            heartbeat: Duration::from_secs(15),
+            retry: None,
+        }
+    }
+
+    /// How often an idle stream gets a comment line, which also detects clients that left.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> Broadcaster {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// A `retry:` value sent when a client connects.
+    pub fn with_retry(mut self, retry: Duration) -> Broadcaster {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Sends `event` to all subscribers and returns the id it was given.
+    pub fn publish(&self, mut event: Event) -> u64 {
+        let mut state = self.shared.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+        event.id = Some(id);
+
+        if state.replay.len() == state.capacity {
+            state.replay.pop_front();
+        }
+        state.replay.push_back(event);
+
+        self.shared.published.notify_all();
+        id
+    }
+
+    /// Ends every open stream, e.g. before shutting the server down.
+    pub fn close(&self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.published.notify_all();
+    }
+
+    /// Builds the `text/event-stream` response that subscribes the client.
+    ///
+    /// A `Last-Event-ID` header resumes after that event, replaying whatever is still
+    /// buffered; otherwise only events published from now on are sent.
+    pub fn subscribe(&self, request: &Request) -> Response {
+        let last_seen = request
+            .header("Last-Event-ID")
+            .and_then(|id| id.trim().parse::<u64>().ok());
+
+        // taken now rather than when streaming starts, so nothing published after the
+        // client has seen the response head can be missed
+        let latest = self.shared.state.lock().unwrap().next_id - 1;
+        // an id from the future (e.g. from before a restart) resumes from now
+        let last_sent = last_seen.map_or(latest, |id| id.min(latest));
+        let broadcaster = self.clone();
+
+        Response::stream(200, move |writer| broadcaster.stream(writer, last_sent))
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+    }
+
+    fn stream(&self, writer: &mut dyn Write, mut last_sent: u64) -> io::Result<()> {
+        if let Some(retry) = self.retry {
+            write!(writer, "retry: {}\n\n", retry.as_millis())?;
+            writer.flush()?;
+        }
+
+        loop {
+            let pending = {
+                let state = self.shared.state.lock().unwrap();
+                let (state, timeout) = self
+                    .shared
+                    .published
+                    .wait_timeout_while(state, self.heartbeat, |state| {
+                        !state.closed && state.next_id - 1 <= last_sent
+                    })
+                    .unwrap();
+
+                if state.closed {
+                    return Ok(());
+                }
+                if timeout.timed_out() {
+                    None
+                } else {
+                    let events: Vec<Event> = state
+                        .replay
+                        .iter()
+                        .filter(|e| e.id > Some(last_sent))
+                        .cloned()
+                        .collect();
+                    last_sent = state.next_id - 1;
+                    Some(events)
+                }
+            };
+
+            match pending {
+                Some(events) => {
+                    for event in events {
+                        write!(writer, "{event}")?;
+                    }
+                }
+                // comments are ignored by clients but fail once the connection is gone
+                None => writer.write_all(b": heartbeat\n\n")?,
+            }
+            writer.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_all_fields() {
+        let event = Event {
+            id: Some(7),
+            ..Event::new("line one\nline two")
+                .event("update")
+                .retry(Duration::from_secs(3))
+        };
+
+        assert_eq!(
+            "event: update\nid: 7\nretry: 3000\ndata: line one\ndata: line two\n\n",
+            event.to_string()
+        );
+    }
+
+    #[test]
+    fn replay_buffer_is_bounded() {
+        let broadcaster = Broadcaster::new(2);
+        for i in 0..5 {
+            broadcaster.publish(Event::new(i.to_string()));
+        }
+
+        let state = broadcaster.shared.state.lock().unwrap();
+        let ids: Vec<_> = state.replay.iter().map(|e| e.id.unwrap()).collect();
+        assert_eq!(vec![4, 5], ids);
+    }
+}
@@ -0,0 +1,418 @@
+//! Small async tasks without a runtime: the tasks behind
+//! [`ThreadPool::spawn_future`](crate::hello::ThreadPool::spawn_future),
+//! [`block_on`], and the [`sleep`] and [`channel`] futures to write them with.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// a poisoned lock only means a future panicked while polled; the data is still fine
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A spawned future, polled by whichever worker its waker schedules it on.
+pub(crate) struct Task {
+    // `None` once the future has finished or panicked
+    future: Mutex<Option<BoxFuture>>,
+    // set while the task sits in the queue, so a flurry of wakes queues it once
+    scheduled: AtomicBool,
+    schedule: Box<dyn Fn(Arc<Task>) + Send + Sync>,
+}
+
+impl Task {
+    /// Spawns `future`, handing it to `schedule` whenever it should be polled.
+    pub(crate) fn spawn<F, S>(future: F, schedule: S) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+        S: Fn(Arc<Task>) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = channel();
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(async move {
+                let _ = sender.send(future.await);
+            }))),
+            scheduled: AtomicBool::new(false),
+            schedule: Box::new(schedule),
+        });
+
+        task.wake();
+        JoinHandle { receiver }
+    }
+
+    /// Polls the future once. A future that panics is dropped, so its
+    /// [`JoinHandle`] gives `None`.
+    pub(crate) fn run(self: Arc<Task>) {
+        // cleared before polling, so a wake during the poll queues the task again
+        self.scheduled.store(false, atomic::Ordering::SeqCst);
+
+        let mut future = lock(&self.future);
+        let Some(running) = future.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+
+        match panic::catch_unwind(AssertUnwindSafe(|| running.as_mut().poll(&mut cx))) {
+            Ok(Poll::Pending) => {}
+            Ok(Poll::Ready(())) => *future = None,
+            Err(_) => {
+                eprintln!("A spawned future panicked");
+                *future = None;
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Task>) {
+        if !self.scheduled.swap(true, atomic::Ordering::SeqCst) {
+            (self.schedule)(Arc::clone(&self));
+        }
+    }
+}
+
+/// The result of a spawned future, itself a future.
+pub struct JoinHandle<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Blocks this thread until the task is done; `None` if it panicked or was
+    /// dropped with the pool before finishing.
+    pub fn join(mut self) -> Option<T> {
+        block_on(self.receiver.recv())
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+// wakes a thread parked in `block_on`
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<ThreadWaker>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on this thread, sleeping while it waits.
+///
+/// Don't call this from a pool's worker while the future waits on other tasks
+/// of the same pool: the worker it blocks might be the one they need.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            // a wake that came before parking makes park return at once
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// A future that completes once `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: Instant::now() + duration,
+        waker: None,
+    }
+}
+
+/// The future returned by [`sleep`].
+pub struct Sleep {
+    deadline: Instant,
+    // shared with the timer thread once the sleep has been polled
+    waker: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        match &self.waker {
+            // the task may have moved, so the latest waker is the one to use
+            Some(slot) => *lock(slot) = Some(cx.waker().clone()),
+            None => {
+                let slot = Arc::new(Mutex::new(Some(cx.waker().clone())));
+                timers().add(self.deadline, Arc::clone(&slot));
+                self.waker = Some(slot);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // the timer thread still fires the entry, but finds nobody to wake
+        if let Some(slot) = &self.waker {
+            lock(slot).take();
+        }
+    }
+}
+
+// the sleeps in progress, fired by one thread shared by the whole process
+struct Timers {
+    entries: Mutex<BinaryHeap<Reverse<Timer>>>,
+    changed: Condvar,
+}
+
+struct Timer {
+    deadline: Instant,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+// ordered by deadline only, for the heap
+impl PartialEq for Timer {
+    fn eq(&self, other: &Timer) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Timer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Timer) -> Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+fn timers() -> &'static Timers {
+    static TIMERS: OnceLock<&'static Timers> = OnceLock::new();
+    TIMERS.get_or_init(|| {
+        let timers: &'static Timers = Box::leak(Box::new(Timers {
+            entries: Mutex::new(BinaryHeap::new()),
+            changed: Condvar::new(),
+        }));
+        thread::Builder::new()
+            .name("timers".to_string())
+            .spawn(move || timers.run())
+            .expect("failed to start the timer thread");
+        timers
+    })
+}
+
+impl Timers {
+    fn add(&self, deadline: Instant, waker: Arc<Mutex<Option<Waker>>>) {
+        lock(&self.entries).push(Reverse(Timer { deadline, waker }));
+        // the new deadline may be sooner than the one the thread is waiting for
+        self.changed.notify_one();
+    }
+
+    fn run(&self) {
+        let mut entries = lock(&self.entries);
+        loop {
+            let now = Instant::now();
+            while entries.peek().is_some_and(|Reverse(t)| t.deadline <= now) {
+                if let Some(Reverse(timer)) = entries.pop() {
+                    if let Some(waker) = lock(&timer.waker).take() {
+                        waker.wake();
+                    }
+                }
+            }
+
+            entries = match entries.peek() {
+                Some(Reverse(next)) => {
+                    let timeout = next.deadline.saturating_duration_since(now);
+                    self.changed
+                        .wait_timeout(entries, timeout)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self
+                    .changed
+                    .wait(entries)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+/// An unbounded channel whose receiving end is a future, for passing values
+/// between tasks, or from threads to tasks.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Mutex::new(Channel {
+        queue: VecDeque::new(),
+        waker: None,
+        senders: 1,
+        receiving: true,
+    }));
+
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Channel<T> {
+    queue: VecDeque<T>,
+    // the task waiting in `recv`
+    waker: Option<Waker>,
+    senders: usize,
+    receiving: bool,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Mutex<Channel<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `value`, or hands it back if the receiver is gone. Never waits.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        let mut channel = lock(&self.shared);
+        if !channel.receiving {
+            return Err(value);
+        }
+        channel.queue.push_back(value);
+        let waker = channel.waker.take();
+        // woken outside the lock, as waking may run the receiver's poll right away
+        drop(channel);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        lock(&self.shared).senders += 1;
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = lock(&self.shared);
+        channel.senders -= 1;
+        // the receiver must find out nothing more is coming
+        let waker = match channel.senders {
+            0 => channel.waker.take(),
+            _ => None,
+        };
+        drop(channel);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Mutex<Channel<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value; `None` once every sender is gone and the queue is empty.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { receiver: self }
+    }
+
+    /// The next value if one is queued, without waiting.
+    pub fn try_recv(&mut self) -> Option<T> {
+        lock(&self.shared).queue.pop_front()
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut channel = lock(&self.shared);
+        match channel.queue.pop_front() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if channel.senders == 0 => Poll::Ready(None),
+            None => {
+                channel.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut channel = lock(&self.shared);
+        channel.receiving = false;
+        channel.queue.clear();
+    }
+}
+
+/// The future returned by [`Receiver::recv`].
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleeps_at_least_as_long_as_asked() {
+        let started = Instant::now();
+        block_on(async {
+            sleep(Duration::from_millis(50)).await;
+            sleep(Duration::from_millis(10)).await;
+        });
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn channel_ends_when_the_senders_are_gone() {
+        let (sender, mut receiver) = channel();
+        let other = sender.clone();
+
+        thread::spawn(move || {
+            sender.send(1).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            other.send(2).unwrap();
+        });
+
+        assert_eq!(Some(1), block_on(receiver.recv()));
+        assert_eq!(Some(2), block_on(receiver.recv()));
+        assert_eq!(None, block_on(receiver.recv()));
+
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(Err("lost"), sender.send("lost"));
+    }
+}
@@ -0,0 +1,659 @@
+//! A small template engine for HTML pages.
+//!
+//! Supported syntax:
+//!
+//! - `{{ user.name }}` interpolates a value from the context, HTML-escaped;
+//!   `{{ html | safe }}` skips the escaping
+//! - `{% if flag %}…{% else %}…{% endif %}`, where `not flag` negates the test
+//! - `{% for item in items %}…{% endfor %}`, with `loop.index` (from 1),
+//!   `loop.first` and `loop.last` available inside the body
+//! - `{% include "partial.html" %}`
+//! - `{% extends "layout.html" %}` together with `{% block name %}…{% endblock %}`
+//! - `{# comments #}`
+//!
+//! The context is a [`json::Value`], so values can be built with `json::Value::object`.
+
+use crate::http::Response;
+use crate::json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+// guards against templates that include or extend themselves
+const MAX_NESTING: usize = 16;
+
+/// Loads templates from a directory and caches them after the first parse.
+pub struct Templates {
+    dir: PathBuf,
+    reload: bool,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+struct Cached {
+    template: Arc<Template>,
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    pub fn new(dir: impl Into<PathBuf>) -> Templates {
+        Templates {
+            dir: dir.into(),
+            reload: false,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// In dev mode a template is re-parsed whenever its file's modification time changes.
+    pub fn with_reload(mut self, reload: bool) -> Templates {
+        self.reload = reload;
+        self
+    }
+
+    /// Renders the template `name` (relative to the template directory).
+    pub fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        let mut renderer = Renderer {
+            templates: self,
+            scopes: vec![Scope::Root(context)],
+            blocks: HashMap::new(),
+            depth: 0,
+        };
+        renderer.template(name, &mut out)?;
+        Ok(out)
+    }
+
+    fn load(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        if name.split(['/', '\\']).any(|part| part == "..") {
+            return Err(TemplateError::NotFound(name.to_string()));
+        }
+
+        let path = self.dir.join(name);
+        let modified = if self.reload {
+            fs::metadata(&path).and_then(|m| m.modified()).ok()
+        } else {
+            None
+        };
+
+        // the lock isn't held while reading the file, so two threads may both parse a
+        // template the first time; the second result simply replaces the first
+        if let Some(cached) = self.cache.lock().unwrap().get(name) {
+            if !self.reload || cached.modified == modified {
+                return Ok(Arc::clone(&cached.template));
+            }
+        }
+
+        let source = fs::read_to_string(&path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => TemplateError::NotFound(name.to_string()),
+            _ => TemplateError::Io(name.to_string(), e),
+        })?;
+        let template = Arc::new(Template::parse(name, &source)?);
+
+        self.cache.lock().unwrap().insert(
+            name.to_string(),
+            Cached {
+                template: Arc::clone(&template),
+                modified,
+            },
+        );
+
+        Ok(template)
+    }
+}
+
+static TEMPLATES: OnceLock<Templates> = OnceLock::new();
+
+/// Sets the templates used by [`render`]; returns them back if already set.
+pub fn configure(templates: Templates) -> Result<(), Templates> {
+    TEMPLATES.set(templates)
+}
+
+/// Renders a template into a 200 response, or fails with the [`TemplateError`]
+/// for the caller to answer; returned from a handler it becomes a 500 error page.
+///
+/// Unless [`configure`] was called first, templates are read from `templates/`
+/// and reloaded on change in debug builds.
+pub fn render(name: &str, context: &Value) -> Result<Response, TemplateError> {
+    let templates =
+        TEMPLATES.get_or_init(|| Templates::new("templates").with_reload(cfg!(debug_assertions)));
+
+    templates
+        .render(name, context)
+        .map(|html| Response::html(200, html))
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    NotFound(String),
+    Io(String, io::Error),
+    Syntax {
+        template: String,
+        line: usize,
+        message: String,
+    },
+    TooDeep(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TemplateError::NotFound(name) => write!(f, "template {name} not found"),
+            TemplateError::Io(name, e) => write!(f, "reading template {name}: {e}"),
+            TemplateError::Syntax {
+                template,
+                line,
+                message,
+            } => write!(f, "{template}:{line}: {message}"),
+            TemplateError::TooDeep(name) => {
+                write!(f, "includes or layouts nested too deeply in {name}")
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+#[derive(Debug)]
+struct Template {
+    extends: Option<String>,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var {
+        path: Vec<String>,
+        safe: bool,
+    },
+    If {
+        path: Vec<String>,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        binding: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+    },
+    Include(String),
+    Block {
+        name: String,
+        body: Arc<Vec<Node>>,
+    },
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    Tag(&'a str),
+}
+
+impl Template {
+    fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+        let tokens = tokenize(name, source)?;
+        let mut parser = TemplateParser {
+            name,
+            tokens: tokens.into_iter().peekable(),
+            extends: None,
+        };
+
+        let (nodes, end) = parser.nodes()?;
+        if let Some((tag, line)) = end {
+            return Err(syntax(name, line, format!("unexpected {{% {tag} %}}")));
+        }
+
+        Ok(Template {
+            extends: parser.extends,
+            nodes,
+        })
+    }
+}
+
+fn syntax(template: &str, line: usize, message: impl Into<String>) -> TemplateError {
+    TemplateError::Syntax {
+        template: template.to_string(),
+        line,
+        message: message.into(),
+    }
+}
+
+// splits the source into text, `{{ }}` and `{% %}` tokens, each tagged with its line
+fn tokenize<'a>(name: &str, source: &'a str) -> Result<Vec<(Token<'a>, usize)>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while !rest.is_empty() {
+        let next = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open))
+            .min();
+
+        let Some(start) = next else {
+            tokens.push((Token::Text(rest), line));
+            break;
+        };
+
+        if start > 0 {
+            tokens.push((Token::Text(&rest[..start]), line));
+            line += rest[..start].matches('\n').count();
+        }
+
+        let close = match &rest[start..start + 2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let end = rest[start + 2..]
+            .find(close)
+            .map(|i| start + 2 + i)
+            .ok_or_else(|| syntax(name, line, format!("missing closing {close}")))?;
+
+        let inner = rest[start + 2..end].trim();
+        match close {
+            "}}" => tokens.push((Token::Var(inner), line)),
+            "%}" => tokens.push((Token::Tag(inner), line)),
+            _ => {} // comment
+        }
+
+        line += rest[start..end].matches('\n').count();
+        rest = &rest[end + 2..];
+    }
+
+    Ok(tokens)
+}
+
+// the parsed nodes plus the closing tag (and its line) that ended them, if any
+type Parsed<'a> = (Vec<Node>, Option<(&'a str, usize)>);
+
+struct TemplateParser<'a, I: Iterator<Item = (Token<'a>, usize)>> {
+    name: &'a str,
+    tokens: std::iter::Peekable<I>,
+    extends: Option<String>,
+}
+
+impl<'a, I: Iterator<Item = (Token<'a>, usize)>> TemplateParser<'a, I> {
+    // parses nodes until an `else`/`end…` tag, which is returned to the caller
+    fn nodes(&mut self) -> Result<Parsed<'a>, TemplateError> {
+        let mut nodes = Vec::new();
+
+        while let Some((token, line)) = self.tokens.next() {
+            match token {
+                Token::Text(text) => nodes.push(Node::Text(text.to_string())),
+                Token::Var(expr) => {
+                    let (expr, safe) = match expr.split_once('|') {
+                        Some((expr, filter)) if filter.trim() == "safe" => (expr.trim(), true),
+                        Some(_) => return Err(syntax(self.name, line, "unknown filter")),
+                        None => (expr, false),
+                    };
+                    nodes.push(Node::Var {
+                        path: self.path(expr, line)?,
+                        safe,
+                    });
+                }
+                Token::Tag(tag) => {
+                    let (keyword, args) = tag.split_once(' ').unwrap_or((tag, ""));
+                    let args = args.trim();
+
+                    match keyword {
+                        "if" => nodes.push(self.if_node(args, line)?),
+                        "for" => nodes.push(self.for_node(args, line)?),
+                        "include" => nodes.push(Node::Include(self.quoted(args, line)?)),
+                        "block" => nodes.push(self.block_node(args, line)?),
+                        "extends" => self.extends = Some(self.quoted(args, line)?),
+                        "else" | "endif" | "endfor" | "endblock" => {
+                            return Ok((nodes, Some((keyword, line))))
+                        }
+                        _ => return Err(syntax(self.name, line, format!("unknown tag {keyword}"))),
+                    }
+                }
+            }
+        }
+
+        Ok((nodes, None))
+    }
+
+    fn expect_end(
+        &self,
+        end: Option<(&str, usize)>,
+        wanted: &str,
+        line: usize,
+    ) -> Result<(), TemplateError> {
+        match end {
+            Some((tag, _)) if tag == wanted => Ok(()),
+            Some((tag, line)) => Err(syntax(
+                self.name,
+                line,
+                format!("expected {wanted}, found {tag}"),
+            )),
+            None => Err(syntax(self.name, line, format!("missing {{% {wanted} %}}"))),
+        }
+    }
+
+    fn if_node(&mut self, args: &str, line: usize) -> Result<Node, TemplateError> {
+        let (negate, expr) = match args.strip_prefix("not ") {
+            Some(expr) => (true, expr.trim()),
+            None => (false, args),
+        };
+        let path = self.path(expr, line)?;
+
+        let (then, end) = self.nodes()?;
+        let otherwise = if let Some(("else", _)) = end {
+            let (otherwise, end) = self.nodes()?;
+            self.expect_end(end, "endif", line)?;
+            otherwise
+        } else {
+            self.expect_end(end, "endif", line)?;
+            Vec::new()
+        };
+
+        Ok(Node::If {
+            path,
+            negate,
+            then,
+            otherwise,
+        })
+    }
+
+    fn for_node(&mut self, args: &str, line: usize) -> Result<Node, TemplateError> {
+        let mut words = args.split_whitespace();
+        let (binding, path) = match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(binding), Some("in"), Some(expr), None) => (binding, self.path(expr, line)?),
+            _ => return Err(syntax(self.name, line, "expected `for item in items`")),
+        };
+
+        let (body, end) = self.nodes()?;
+        self.expect_end(end, "endfor", line)?;
+
+        Ok(Node::For {
+            binding: binding.to_string(),
+            path,
+            body,
+        })
+    }
+
+    fn block_node(&mut self, args: &str, line: usize) -> Result<Node, TemplateError> {
+        if args.is_empty() || args.contains(char::is_whitespace) {
+            return Err(syntax(self.name, line, "expected a block name"));
+        }
+
+        let (body, end) = self.nodes()?;
+        self.expect_end(end, "endblock", line)?;
+
+        Ok(Node::Block {
+            name: args.to_string(),
+            body: Arc::new(body),
+        })
+    }
+
+    fn path(&self, expr: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+        let valid =
+            |part: &str| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_');
+
+        let path: Vec<String> = expr.trim().split('.').map(str::to_string).collect();
+        if path.iter().all(|part| valid(part)) {
+            Ok(path)
+        } else {
+            Err(syntax(
+                self.name,
+                line,
+                format!("invalid expression `{expr}`"),
+            ))
+        }
+    }
+
+    fn quoted(&self, args: &str, line: usize) -> Result<String, TemplateError> {
+        args.strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .map(str::to_string)
+            .ok_or_else(|| syntax(self.name, line, "expected a quoted template name"))
+    }
+}
+
+enum Scope<'a> {
+    Root(&'a Value),
+    Binding(String, Value),
+}
+
+struct Renderer<'a> {
+    templates: &'a Templates,
+    scopes: Vec<Scope<'a>>,
+    // the innermost (most derived) definition of each block wins
+    blocks: HashMap<String, Arc<Vec<Node>>>,
+    depth: usize,
+}
+
+impl Renderer<'_> {
+    fn template(&mut self, name: &str, out: &mut String) -> Result<(), TemplateError> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return Err(TemplateError::TooDeep(name.to_string()));
+        }
+
+        let template = self.templates.load(name)?;
+
+        match &template.extends {
+            // a child only contributes blocks; the layout produces the output
+            Some(layout) => {
+                collect_blocks(&template.nodes, &mut self.blocks);
+                self.template(layout, out)?;
+            }
+            None => self.nodes(&template.nodes, out)?,
+        }
+
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn nodes(&mut self, nodes: &[Node], out: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var { path, safe } => {
+                    let text = self.lookup(path).map(display).unwrap_or_default();
+                    if *safe {
+                        out.push_str(&text);
+                    } else {
+                        escape_html_into(&text, out);
+                    }
+                }
+                Node::If {
+                    path,
+                    negate,
+                    then,
+                    otherwise,
+                } => {
+                    let truthy = self.lookup(path).is_some_and(is_truthy);
+                    if truthy != *negate {
+                        self.nodes(then, out)?;
+                    } else {
+                        self.nodes(otherwise, out)?;
+                    }
+                }
+                Node::For {
+                    binding,
+                    path,
+                    body,
+                } => {
+                    let items = match self.lookup(path) {
+                        Some(Value::Array(items)) => items.clone(),
+                        _ => Vec::new(),
+                    };
+                    let count = items.len();
+
+                    for (i, item) in items.into_iter().enumerate() {
+                        let info = Value::object([
+                            ("index", Value::from(i + 1)),
+                            ("first", Value::from(i == 0)),
+                            ("last", Value::from(i + 1 == count)),
+                        ]);
+                        self.scopes.push(Scope::Binding("loop".to_string(), info));
+                        self.scopes.push(Scope::Binding(binding.clone(), item));
+                        let result = self.nodes(body, out);
+                        self.scopes.truncate(self.scopes.len() - 2);
+                        result?;
+                    }
+                }
+                Node::Include(name) => self.template(name, out)?,
+                Node::Block { name, body } => {
+                    let body = self
+                        .blocks
+                        .get(name)
+                        .cloned()
+                        .unwrap_or_else(|| Arc::clone(body));
+                    self.nodes(&body, out)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // resolves `a.b.c`, looking at loop bindings (innermost first) before the context
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+
+        let mut value = self.scopes.iter().rev().find_map(|scope| match scope {
+            Scope::Binding(name, value) if name == first => Some(value),
+            Scope::Root(context) => context.get(first),
+            Scope::Binding(..) => None,
+        })?;
+
+        for key in rest {
+            value = value.get(key)?;
+        }
+
+        Some(value)
+    }
+}
+
+fn collect_blocks(nodes: &[Node], blocks: &mut HashMap<String, Arc<Vec<Node>>>) {
+    for node in nodes {
+        if let Node::Block { name, body } = node {
+            // blocks nested inside an overriding block belong to it, so they aren't collected
+            blocks
+                .entry(name.clone())
+                .or_insert_with(|| Arc::clone(body));
+        }
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => *n != 0.0,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(members) => !members.is_empty(),
+    }
+}
+
+/// Escapes the characters that are significant in HTML text and attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    escape_html_into(text, &mut out);
+    out
+}
+
+fn escape_html_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::{temp_dir, TempDir};
+
+    // the directory goes when the test is done with it
+    fn templates(files: &[(&str, &str)]) -> (TempDir, Templates) {
+        let dir = temp_dir(&format!("templates_{}", files[0].0));
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let templates = Templates::new(dir.to_path_buf());
+        (dir, templates)
+    }
+
+    #[test]
+    fn interpolates_and_escapes() {
+        let (_dir, templates) =
+            templates(&[("escape.html", "<p>{{ user.name }}</p>{{ raw | safe }}")]);
+        let context = Value::object([
+            ("user", Value::object([("name", "<Ferris & co>".into())])),
+            ("raw", "<b>bold</b>".into()),
+        ]);
+
+        assert_eq!(
+            "<p>&lt;Ferris &amp; co&gt;</p><b>bold</b>",
+            templates.render("escape.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn renders_conditionals_and_loops() {
+        let (_dir, templates) = templates(&[(
+            "loops.html",
+            "{% for c in crates %}{% if not loop.first %}, {% endif %}{{ loop.index }}:{{ c }}{% endfor %}\
+             {% if missing %}yes{% else %}.{% endif %}",
+        )]);
+        let context = Value::object([("crates", vec!["std", "core"].into())]);
+
+        assert_eq!(
+            "1:std, 2:core.",
+            templates.render("loops.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn fills_layout_blocks_and_includes() {
+        let (_dir, templates) = templates(&[
+            (
+                "page.html",
+                "{% extends \"base.html\" %}{% block body %}hi {{ who }}{% endblock %}",
+            ),
+            (
+                "base.html",
+                "<{% block body %}default{% endblock %}>{% include \"foot.html\" %}",
+            ),
+            ("foot.html", "!"),
+        ]);
+        let context = Value::object([("who", "there".into())]);
+
+        assert_eq!(
+            "<hi there>!",
+            templates.render("page.html", &context).unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_unclosed_blocks() {
+        let (_dir, templates) = templates(&[("broken.html", "a\n{% if x %}b")]);
+
+        let err = templates.render("broken.html", &Value::Null).unwrap_err();
+        assert_eq!("broken.html:2: missing {% endif %}", err.to_string());
+    }
+}
@@ -0,0 +1,242 @@
+//! Virtual hosts: several sites served by one server, chosen by the `Host` header.
+
+use crate::date;
+use crate::error::{ErrorPages, ServerError};
+use crate::files;
+use crate::http::{self, Request, Response};
+use crate::middleware;
+use crate::server::Router;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Default)]
+pub struct HostConfig {
+    /// The names this host answers to, compared case-insensitively and without a port.
+    pub names: Vec<String>,
+    /// Static files are served from here for requests no route matches.
+    pub root: Option<PathBuf>,
+    /// Pages sent in place of the built-in error pages, by status; relative paths
+    /// are resolved against `root`.
+    pub error_pages: HashMap<u16, PathBuf>,
+    /// URL paths under which directories without an `index.html` get a generated
+    /// listing, e.g. `/downloads`; `/` enables listings for the whole site.
+    pub listings: Vec<String>,
+    /// Serves files and directories whose names start with `.`, which are refused
+    /// otherwise: they tend to be private, like `.env` or `.git`.
+    pub dotfiles: bool,
+    /// An access log, appended to in the Combined Log Format.
+    pub log: Option<PathBuf>,
+    /// Gets requests whose `Host` matches no other host; otherwise the first host does.
+    pub default: bool,
+}
+
+pub struct VirtualHost {
+    config: HostConfig,
+    router: Arc<Router>,
+    log: Option<Mutex<File>>,
+}
+
+impl VirtualHost {
+    /// Opens the host's log file, if it has one.
+    pub fn new(config: HostConfig, router: Arc<Router>) -> io::Result<VirtualHost> {
+        let log = match &config.log {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Some(Mutex::new(file))
+            }
+            None => None,
+        };
+
+        Ok(VirtualHost {
+            config,
+            router,
+            log,
+        })
+    }
+
+    pub fn config(&self) -> &HostConfig {
+        &self.config
+    }
+
+    /// Routes the request, then tries the document root, then the router's fallback,
+    /// all inside the router's middleware.
+    ///
+    /// Errors, including handler panics, are answered with the host's error page for
+    /// the status, else the one from `fallback_pages`, else a built-in page.
+    pub fn handle(&self, request: &mut Request, fallback_pages: ErrorPages) -> Response {
+        let response = middleware::run(self.router.middleware(), request, |request| {
+            self.respond(request, fallback_pages)
+        });
+
+        self.log(request, &response);
+        response
+    }
+
+    fn respond(&self, request: &Request, fallback_pages: ErrorPages) -> Response {
+        // a panicking handler must not take the worker thread down with it
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.router
+                .dispatch(request)
+                .or_else(|| {
+                    let root = self.config.root.as_ref()?;
+                    let list = self
+                        .config
+                        .listings
+                        .iter()
+                        .any(|prefix| http::path_within(&request.path, prefix));
+                    files::serve(root, request, list, self.config.dotfiles).map(Ok)
+                })
+                .unwrap_or_else(|| self.router.handle_unmatched(request))
+        }))
+        .unwrap_or_else(|payload| Err(ServerError::from_panic(payload)));
+
+        result.unwrap_or_else(|e| {
+            if e.status() == Some(500) {
+                eprintln!("Error handling {} {}: {e}", request.method, request.path);
+            }
+            let own_pages = ErrorPages::new(&self.config.error_pages, self.config.root.as_deref());
+            e.to_response(&[own_pages, fallback_pages])
+        })
+    }
+
+    fn log(&self, request: &Request, response: &Response) {
+        let Some(log) = &self.log else {
+            return;
+        };
+
+        let target = match request.query.as_str() {
+            "" => request.path.clone(),
+            query => format!("{}?{query}", request.path),
+        };
+        let length = match response.stream {
+            Some(_) => "-".to_string(),
+            None => response.body.len().to_string(),
+        };
+        let quoted = |name| request.header(name).unwrap_or("-").replace('"', "\\\"");
+
+        let line = format!(
+            "{} - - [{}] \"{} {target} {}\" {} {length} \"{}\" \"{}\"\n",
+            request
+                .peer
+                .map_or("-".to_string(), |peer| peer.ip().to_string()),
+            date::log_date(SystemTime::now()),
+            request.method,
+            request.version,
+            response.status,
+            quoted("Referer"),
+            quoted("User-Agent"),
+        );
+
+        // a poisoned lock only means another write panicked; the file is still usable
+        let mut file = log.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            eprintln!("Failed to write access log: {e}");
+        }
+    }
+}
+
+/// The table of hosts a server picks from for each request.
+pub struct Hosts {
+    hosts: Vec<VirtualHost>,
+    default: usize,
+}
+
+impl Hosts {
+    /// Creates a host for each config, asking `router_for` for its routes.
+    ///
+    /// With no configs at all, a single catch-all host without a document root is used.
+    pub fn build<F>(configs: &[HostConfig], mut router_for: F) -> io::Result<Hosts>
+    where
+        F: FnMut(&HostConfig) -> Arc<Router>,
+    {
+        let fallback = [HostConfig::default()];
+        let configs = if configs.is_empty() {
+            &fallback[..]
+        } else {
+            configs
+        };
+
+        if configs.iter().filter(|c| c.default).count() > 1 {
+            return Err(invalid("more than one default host"));
+        }
+        let mut seen = HashSet::new();
+        for name in configs.iter().flat_map(|c| &c.names) {
+            if !seen.insert(normalize(name)) {
+                return Err(invalid(&format!("host name {name} is used twice")));
+            }
+        }
+
+        let hosts = configs
+            .iter()
+            .map(|config| VirtualHost::new(config.clone(), router_for(config)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let default = configs.iter().position(|c| c.default).unwrap_or(0);
+
+        Ok(Hosts { hosts, default })
+    }
+
+    /// Picks the host for a request, or `None` if the request must be rejected with 400:
+    /// HTTP/1.1 requires exactly one `Host` header.
+    pub fn select(&self, request: &Request) -> Option<&VirtualHost> {
+        let mut values = request.headers.get_all("Host");
+
+        let name = match (values.next(), values.next()) {
+            (Some(value), None) => normalize(value),
+            (None, _) if request.version == "HTTP/1.0" => String::new(),
+            _ => return None,
+        };
+
+        let host = self
+            .hosts
+            .iter()
+            .find(|host| host.config.names.iter().any(|n| normalize(n) == name));
+
+        Some(host.unwrap_or(&self.hosts[self.default]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualHost> {
+        self.hosts.iter()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// "Example.COM:8080" -> "example.com", "[::1]:80" -> "[::1]", "example.com." -> "example.com"
+fn normalize(host: &str) -> String {
+    let host = host.trim().to_ascii_lowercase();
+
+    let without_port = if host.starts_with('[') {
+        // an IPv6 literal; the port, if any, follows the closing bracket
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => &host,
+        }
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+            _ => &host,
+        }
+    };
+
+    without_port.trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_host_values() {
+        assert_eq!("example.com", normalize("Example.COM:8080"));
+        assert_eq!("example.com", normalize(" example.com. "));
+        assert_eq!("[::1]", normalize("[::1]:7878"));
+        assert_eq!("localhost", normalize("localhost:"));
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{request, TestServer};
+use multithreaded_server::listener::Endpoint;
+use multithreaded_server::routes;
+use multithreaded_server::server::{Config, Server};
+use multithreaded_server::sse::Broadcaster;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn start(sleep: Duration) -> (TestServer, SocketAddr) {
+    let config = Config {
+        workers: 2,
+        admin: Some("127.0.0.1:0".parse().unwrap()),
+        ..Config::default()
+    };
+    let server = Server::bind(
+        "127.0.0.1:0",
+        config,
+        routes::router(sleep, Arc::default(), Broadcaster::new(100)),
+    )
+    .unwrap();
+    let admin = match server.admin_endpoint() {
+        Some(Endpoint::Tcp(addr)) => *addr,
+        other => panic!("admin API bound to {other:?}"),
+    };
+    (TestServer::run(server), admin)
+}
+
+fn get(addr: SocketAddr, path: &str) -> common::TestResponse {
+    request(
+        addr,
+        &format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+    )
+}
+
+// polls `path` on the admin API until `done` accepts the body
+fn wait_for(admin: SocketAddr, path: &str, done: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let body = get(admin, path).body;
+        if done(&body) {
+            return body;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "gave up waiting, last saw {body}"
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn answers_health_and_readiness() {
+    let (server, admin) = start(Duration::ZERO);
+
+    let health = get(admin, "/healthz");
+    assert_eq!(200, health.status);
+    assert_eq!(r#"{"status":"ok"}"#, health.body);
+
+    let ready = get(admin, "/readyz");
+    assert_eq!(200, ready.status);
+    assert_eq!(r#"{"ready":true}"#, ready.body);
+
+    // the app's routes aren't on the admin port, nor the admin routes on the app's
+    assert_eq!(404, get(admin, "/").status);
+    assert_eq!(404, server.get("/healthz").status);
+}
+
+#[test]
+fn shows_busy_workers_and_readiness_going_down() {
+    let (server, admin) = start(Duration::from_millis(1500));
+
+    let addr = server.addr;
+    let slow = thread::spawn(move || get(addr, "/sleep"));
+
+    let pool = wait_for(admin, "/admin/pool", |body| body.contains(r#""busy":1"#));
+    assert!(pool.contains(r#""size":2"#), "{pool}");
+    assert!(pool.contains(r#""state":"busy""#), "{pool}");
+    assert!(pool.contains(r#""request":"GET /sleep""#), "{pool}");
+    assert!(pool.contains(r#""state":"idle""#), "{pool}");
+
+    // while the slow request finishes, the server is draining and not ready
+    let stopping = thread::spawn(move || server.stop());
+    wait_for(admin, "/readyz", |body| body.contains("false"));
+    let ready = get(admin, "/readyz");
+    assert_eq!(503, ready.status);
+    assert_eq!(r#"{"ready":false}"#, ready.body);
+
+    assert_eq!(200, slow.join().unwrap().status);
+    stopping.join().unwrap();
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::TestServer;
+use multithreaded_server::routes;
+use multithreaded_server::server::Config;
+use multithreaded_server::sse::Broadcaster;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn bench(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bench"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn reports_a_fixed_number_of_requests() {
+    let config = Config {
+        keep_alive: Some(Duration::from_secs(5)),
+        ..Config::default()
+    };
+    let server = TestServer::start(
+        config,
+        routes::router(Duration::ZERO, Arc::default(), Broadcaster::new(100)),
+    );
+    let url = format!("http://{}/", server.addr);
+
+    for keep_alive in [&[][..], &["-k"]] {
+        let mut args = vec!["-c", "4", "-n", "40", &url];
+        args.extend(keep_alive);
+        let report = bench(&args);
+
+        assert!(report.contains("Requests:  40 in"), "{report}");
+        assert!(report.contains("Statuses:  200: 40"), "{report}");
+        assert!(
+            report.contains("Errors:    0 connect, 0 read/write, 0 timeout, 0 4xx/5xx"),
+            "{report}"
+        );
+    }
+}
+
+#[test]
+fn holds_the_target_rate() {
+    let server = TestServer::start(
+        Config::default(),
+        routes::router(Duration::ZERO, Arc::default(), Broadcaster::new(100)),
+    );
+    let url = format!("http://{}/", server.addr);
+
+    // 20 requests at 40 per second take about half a second
+    let report = bench(&["-c", "2", "-n", "20", "-r", "40", "-k", &url]);
+    let seconds: f64 = report
+        .split("Requests:  20 in ")
+        .nth(1)
+        .and_then(|rest| rest.split('s').next())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| panic!("{report}"));
+    assert!((0.4..2.0).contains(&seconds), "{report}");
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use common::TestServer;
+use multithreaded_server::body::Body;
+use multithreaded_server::client::{Client, ClientError, ClientRequest};
+use multithreaded_server::http::Response;
+use multithreaded_server::server::{Config, Router};
+use std::io::{prelude::*, BufReader};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+fn start() -> TestServer {
+    let router = Router::new()
+        .get("/hello", |_| Response::text(200, "hello"))
+        .route("POST", "/echo", |request| {
+            let body = match &request.body {
+                Body::Raw(bytes) => bytes.clone(),
+                other => other.to_json().to_string().into_bytes(),
+            };
+            Response::new(200).with_body(body).with_header(
+                "X-Token",
+                request.header("X-Token").unwrap_or("none").to_string(),
+            )
+        })
+        .get("/peer", |request| {
+            Response::text(200, request.peer.map(|p| p.port()).unwrap_or(0).to_string())
+        })
+        .route("POST", "/submit", |_| {
+            Response::new(303).with_header("Location", "method")
+        })
+        .get("/method", |request| {
+            Response::text(200, request.method.clone())
+        })
+        .get("/moved", |_| {
+            Response::new(302).with_header("Location", "/hello")
+        })
+        .get("/loop", |_| {
+            Response::new(302).with_header("Location", "/loop")
+        });
+
+    // the client's pool only has something to reuse if the server keeps connections
+    let config = Config {
+        keep_alive: Some(Duration::from_secs(5)),
+        ..Config::default()
+    };
+    TestServer::start(config, router)
+}
+
+#[test]
+fn gets_and_posts() {
+    let server = start();
+    let client = Client::new();
+    let base = format!("http://{}", server.addr);
+
+    let hello = client.get(&format!("{base}/hello")).unwrap();
+    assert_eq!(200, hello.status);
+    assert_eq!(b"hello", &hello.body[..]);
+    assert_eq!(
+        Some("text/plain; charset=utf-8"),
+        hello.headers.get("Content-Type")
+    );
+
+    let echo = client
+        .send(
+            ClientRequest::new("POST", &format!("{base}/echo"))
+                .with_header("Content-Type", "application/octet-stream")
+                .with_header("X-Token", "abc")
+                .with_body("ping"),
+        )
+        .unwrap();
+    assert_eq!(b"ping", &echo.body[..]);
+    assert_eq!(Some("abc"), echo.headers.get("X-Token"));
+
+    assert_eq!(404, client.get(&format!("{base}/nope")).unwrap().status);
+}
+
+#[test]
+fn reuses_connections() {
+    let server = start();
+    let client = Client::new();
+    let url = format!("http://{}/peer", server.addr);
+
+    // the same client port means the same connection
+    let first = client.get(&url).unwrap().body;
+    let second = client.get(&url).unwrap().body;
+    assert_eq!(first, second);
+
+    // a connection left idle too long is replaced
+    let client = Client::new().with_idle_timeout(Duration::ZERO);
+    let first = client.get(&url).unwrap().body;
+    let second = client.get(&url).unwrap().body;
+    assert_ne!(first, second);
+}
+
+#[test]
+fn follows_redirects_up_to_the_limit() {
+    let server = start();
+    let base = format!("http://{}", server.addr);
+
+    let client = Client::new();
+    assert_eq!(
+        b"hello",
+        &client.get(&format!("{base}/moved")).unwrap().body[..]
+    );
+
+    // 303 turns the POST into a GET
+    let after_post = client
+        .post(&format!("{base}/submit"), "text/plain", "data")
+        .unwrap();
+    assert_eq!(b"GET", &after_post.body[..]);
+
+    assert!(matches!(
+        client.get(&format!("{base}/loop")),
+        Err(ClientError::TooManyRedirects(10))
+    ));
+
+    let not_following = Client::new().with_max_redirects(0);
+    let moved = not_following.get(&format!("{base}/moved")).unwrap();
+    assert_eq!(302, moved.status);
+    assert_eq!(Some("/hello"), moved.headers.get("Location"));
+}
+
+// answers each connection's first request with `response` as-is
+fn raw_server(response: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    format!("http://{addr}/")
+}
+
+#[test]
+fn decodes_chunked_bodies() {
+    let url = raw_server(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nX-Trailer: yes\r\n\r\n",
+    );
+
+    let response = Client::new().get(&url).unwrap();
+    assert_eq!(b"hello, world", &response.body[..]);
+
+    let too_big = Client::new().with_max_body_size(8).get(&url);
+    assert!(matches!(too_big, Err(ClientError::TooLarge)));
+
+    // a size that would overflow a running total is still too large
+    let url = raw_server(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+         1\r\na\r\nffffffffffffffff\r\n",
+    );
+    let hostile = Client::new().get(&url);
+    assert!(matches!(hostile, Err(ClientError::TooLarge)));
+}
+
+#[test]
+fn gives_up_on_a_silent_server() {
+    // accepts connections but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    let client = Client::new().with_timeout(Duration::from_millis(200));
+    assert!(matches!(client.get(&url), Err(ClientError::Timeout)));
+
+    assert!(matches!(
+        client.get("https://example.com/"),
+        Err(ClientError::InvalidUrl(_))
+    ));
+}
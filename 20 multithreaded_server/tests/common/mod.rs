@@ -0,0 +1,139 @@
+// shared helpers for the integration tests; not a test crate of its own
+// each test crate only uses some of them
+#![allow(dead_code, unused_imports)]
+
+mod temp_dir;
+
+pub use temp_dir::{temp_dir, TempDir};
+
+use multithreaded_server::server::{Config, Router, Server, ShutdownHandle};
+use std::io::{prelude::*, BufReader};
+use std::net::{SocketAddr, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A server running on an ephemeral port in a background thread.
+///
+/// Dropping it shuts the server down and waits for in-flight requests.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    handle: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    pub fn start(config: Config, router: Router) -> TestServer {
+        // port 0 lets the OS pick a free port, so tests can run in parallel
+        TestServer::run(Server::bind("127.0.0.1:0", config, router).unwrap())
+    }
+
+    /// Runs an already bound server.
+    pub fn run(server: Server) -> TestServer {
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let thread = thread::spawn(move || server.run());
+
+        TestServer {
+            addr,
+            handle,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the server and waits for its accept loop and workers to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.handle.shutdown();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestResponse {
+        request(
+            self.addr,
+            &format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        )
+    }
+
+    pub fn post(&self, path: &str, content_type: &str, body: &str) -> TestResponse {
+        request(
+            self.addr,
+            &format!(
+                "POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        )
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Sends `raw` as-is and reads back one response.
+pub fn request(addr: SocketAddr, raw: &str) -> TestResponse {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    stream.write_all(raw.as_bytes()).unwrap();
+
+    read_response(&mut BufReader::new(stream))
+}
+
+pub fn read_response<R: BufRead>(reader: &mut R) -> TestResponse {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).unwrap();
+    let status = status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| panic!("bad status line {status_line:?}"));
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').unwrap();
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+
+    let length = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("Content-Length"))
+        .map_or(0, |(_, v)| v.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    TestResponse {
+        status,
+        headers,
+        body: String::from_utf8(body).unwrap(),
+    }
+}
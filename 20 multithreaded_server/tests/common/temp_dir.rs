@@ -0,0 +1,40 @@
+// temporary directories for the tests, shared with the unit tests in src/ too,
+// so it only uses the standard library
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+/// An empty directory of its own under the system's temporary directory,
+/// removed with everything in it when dropped.
+pub struct TempDir(PathBuf);
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Makes a [`TempDir`], with `name` telling it apart from the other tests' and
+/// the process id from other runs'.
+pub fn temp_dir(name: &str) -> TempDir {
+    let path = env::temp_dir().join(format!("multithreaded_server_{name}_{}", process::id()));
+    // left over from a run that didn't get to clean up
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+}
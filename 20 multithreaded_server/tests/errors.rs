@@ -0,0 +1,94 @@
+mod common;
+
+use common::{request, temp_dir, TestServer};
+use multithreaded_server::error::ServerError;
+use multithreaded_server::http::Response;
+use multithreaded_server::server::{Config, Router};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{prelude::*, BufReader};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn router() -> Router {
+    Router::new()
+        .get("/", |_| Response::text(200, "fine\n"))
+        .get("/panic", |_| -> Response { panic!("handler blew up") })
+        .get("/fail", |_| -> Result<Response, ServerError> {
+            Err(ServerError::Handler("database unreachable".to_string()))
+        })
+}
+
+#[test]
+fn handler_panic_is_500_and_server_keeps_going() {
+    let server = TestServer::start(Config::default(), router());
+
+    let response = server.get("/panic");
+
+    assert_eq!(500, response.status);
+    assert!(response.body.contains("500 Internal Server Error"));
+    assert!(!response.body.contains("blew up"));
+
+    // the worker survived, so every worker can still answer
+    for _ in 0..Config::default().workers * 2 {
+        assert_eq!(200, server.get("/").status);
+    }
+}
+
+#[test]
+fn handler_errors_get_their_status() {
+    let server = TestServer::start(Config::default(), router());
+
+    assert_eq!(500, server.get("/fail").status);
+
+    let response = server.get("/nope");
+    assert_eq!(404, response.status);
+    assert!(response.body.contains("<h1>404 Not Found</h1>"));
+
+    let response = server.post("/", "text/plain", "hi");
+    assert_eq!(405, response.status);
+    assert_eq!(Some("GET"), response.header("Allow"));
+}
+
+#[test]
+fn serves_configured_error_pages() {
+    let dir = temp_dir("errors");
+    fs::write(dir.join("404.html"), "custom not found").unwrap();
+
+    let config = Config {
+        error_pages: HashMap::from([
+            (404, dir.join("404.html")),
+            // missing, so the built-in page is used instead
+            (500, PathBuf::from("/nonexistent/500.html")),
+            (400, dir.join("400.html")),
+        ]),
+        ..Config::default()
+    };
+    let server = TestServer::start(config, router());
+
+    assert_eq!("custom not found", server.get("/nope").body);
+    assert!(server
+        .get("/fail")
+        .body
+        .contains("500 Internal Server Error"));
+    // rejected before any host or router was involved
+    let response = request(server.addr, "garbage\r\n\r\n");
+    assert_eq!(400, response.status);
+    assert!(response.body.contains("400 Bad Request"));
+}
+
+#[test]
+fn silent_client_gets_408() {
+    let config = Config {
+        read_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let server = TestServer::start(config, router());
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+    let response = common::read_response(&mut BufReader::new(stream));
+    assert_eq!(408, response.status);
+}
@@ -0,0 +1,112 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::{read_response, temp_dir, TestServer};
+use multithreaded_server::http::Response;
+use multithreaded_server::listener::{Endpoint, Listener};
+use multithreaded_server::server::{Config, Router, Server};
+use std::io::{prelude::*, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+fn router() -> Router {
+    Router::new().get("/", |request| {
+        let peer = request
+            .peer
+            .map_or("unix".to_string(), |p| p.ip().to_string());
+        Response::text(200, peer)
+    })
+}
+
+fn get<S: Read + Write>(mut stream: S) -> String {
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    read_response(&mut BufReader::new(stream)).body
+}
+
+#[test]
+fn serves_tcp_and_unix_listeners_together() {
+    let dir = temp_dir("listener_multi");
+    let path = dir.join("server.sock");
+    let mut endpoints: Vec<Endpoint> =
+        vec!["127.0.0.1:0".parse().unwrap(), Endpoint::Unix(path.clone())];
+    // not every sandbox has IPv6
+    let ipv6 = TcpListener::bind("[::1]:0").is_ok();
+    if ipv6 {
+        endpoints.push("[::1]:0".parse().unwrap());
+    }
+
+    let listeners = endpoints
+        .iter()
+        .map(|e| Listener::bind(e).unwrap())
+        .collect();
+    let server = Server::listen(listeners, Config::default(), |_| router()).unwrap();
+    let bound = server.local_endpoints().to_vec();
+    let server = TestServer::run(server);
+
+    for endpoint in &bound {
+        let body = match endpoint {
+            Endpoint::Tcp(addr) => get(TcpStream::connect(addr).unwrap()),
+            Endpoint::Unix(path) => get(UnixStream::connect(path).unwrap()),
+            Endpoint::Fd(_) => unreachable!(),
+        };
+        let expected = match endpoint {
+            Endpoint::Tcp(SocketAddr::V4(_)) => "127.0.0.1",
+            Endpoint::Tcp(SocketAddr::V6(_)) => "::1",
+            _ => "unix",
+        };
+        assert_eq!(expected, body);
+    }
+
+    server.stop();
+    // the socket file is cleaned up with the listener
+    assert!(!path.exists());
+}
+
+#[test]
+fn replaces_stale_unix_socket() {
+    let dir = temp_dir("listener_stale");
+    let path = dir.join("server.sock");
+    // a socket file whose server is gone
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    // the test server is stopped through its TCP listener
+    let listeners = vec![
+        Listener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap(),
+        Listener::bind(&Endpoint::Unix(path.clone())).unwrap(),
+    ];
+    let server =
+        TestServer::run(Server::listen(listeners, Config::default(), |_| router()).unwrap());
+
+    assert_eq!("unix", get(UnixStream::connect(&path).unwrap()));
+    server.stop();
+}
+
+#[test]
+fn serves_inherited_file_descriptor() {
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = tcp.local_addr().unwrap();
+    let fd = tcp.into_raw_fd();
+
+    // SAFETY: into_raw_fd gave up the only owner of `fd`
+    let listener = unsafe { Listener::from_fd(fd) }.unwrap();
+    let server =
+        TestServer::run(Server::listen(vec![listener], Config::default(), |_| router()).unwrap());
+
+    assert_eq!("127.0.0.1", get(TcpStream::connect(addr).unwrap()));
+    server.stop();
+}
+
+#[test]
+fn refuses_fds_not_passed_in() {
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let fd = std::os::fd::AsRawFd::as_raw_fd(&tcp);
+
+    // still owned by `tcp`, which would close it a second time
+    let err = Listener::bind(&Endpoint::Fd(fd)).err().unwrap();
+    assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+}
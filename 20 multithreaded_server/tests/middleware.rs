@@ -0,0 +1,204 @@
+mod common;
+
+use common::{request, temp_dir, TestServer};
+use multithreaded_server::http::{Request, Response};
+use multithreaded_server::middleware::{BasicAuth, Cors, Middleware, RequestId, SecurityHeaders};
+use multithreaded_server::server::{Config, Router};
+use multithreaded_server::vhost::HostConfig;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// records the order hooks ran in, and answers `/blocked` itself
+struct Trace {
+    name: &'static str,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Trace {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("before {}", self.name));
+        (request.path == "/blocked" && self.name == "outer")
+            .then(|| Response::text(403, "Forbidden\n"))
+    }
+
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("after {}", self.name));
+        response
+    }
+}
+
+#[test]
+fn hooks_run_in_order_and_can_short_circuit() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let trace = |name| Trace {
+        name,
+        calls: Arc::clone(&calls),
+    };
+    let router = Router::new()
+        .wrap(trace("outer"))
+        .wrap(trace("inner"))
+        .get("/", |_| Response::text(200, "hi\n"));
+    let server = TestServer::start(Config::default(), router);
+
+    assert_eq!(200, server.get("/").status);
+    assert_eq!(
+        vec!["before outer", "before inner", "after inner", "after outer"],
+        *calls.lock().unwrap()
+    );
+
+    calls.lock().unwrap().clear();
+    assert_eq!(403, server.get("/blocked").status);
+    assert_eq!(vec!["before outer", "after outer"], *calls.lock().unwrap());
+
+    // error pages go through the chain too
+    calls.lock().unwrap().clear();
+    assert_eq!(404, server.get("/nope").status);
+    assert_eq!(4, calls.lock().unwrap().len());
+}
+
+#[test]
+fn cors_answers_preflight_and_marks_responses() {
+    let router = Router::new()
+        .wrap(Cors::new().with_origin("https://app.test"))
+        .post("/api", |_| Response::text(200, "ok\n"));
+    let server = TestServer::start(Config::default(), router);
+
+    let preflight = request(
+        server.addr,
+        "OPTIONS /api HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.test\r\n\
+         Access-Control-Request-Method: POST\r\n\r\n",
+    );
+    assert_eq!(204, preflight.status);
+    assert_eq!(
+        Some("https://app.test"),
+        preflight.header("Access-Control-Allow-Origin")
+    );
+    assert_eq!(
+        Some("GET, POST"),
+        preflight.header("Access-Control-Allow-Methods")
+    );
+
+    let actual = request(
+        server.addr,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nOrigin: https://app.test\r\nContent-Length: 0\r\n\r\n",
+    );
+    assert_eq!(Some("Origin"), actual.header("Vary"));
+    assert_eq!(
+        Some("https://app.test"),
+        actual.header("Access-Control-Allow-Origin")
+    );
+
+    let other = request(
+        server.addr,
+        "POST /api HTTP/1.1\r\nHost: localhost\r\nOrigin: https://evil.test\r\nContent-Length: 0\r\n\r\n",
+    );
+    assert_eq!(None, other.header("Access-Control-Allow-Origin"));
+}
+
+#[test]
+fn adds_request_id_and_security_headers() {
+    let router = Router::new()
+        .wrap(RequestId)
+        .wrap(SecurityHeaders::new().with_hsts(Duration::from_secs(3600)))
+        .get("/", |request| {
+            Response::text(200, request.header("X-Request-Id").unwrap_or("none"))
+        });
+    let server = TestServer::start(Config::default(), router);
+
+    let response = server.get("/");
+    let id = response.header("X-Request-Id").unwrap();
+    assert_eq!(32, id.len());
+    assert_eq!(id, response.body);
+    assert_eq!(Some("nosniff"), response.header("X-Content-Type-Options"));
+    assert_eq!(
+        Some("default-src 'self'"),
+        response.header("Content-Security-Policy")
+    );
+    assert_eq!(
+        Some("max-age=3600; includeSubDomains"),
+        response.header("Strict-Transport-Security")
+    );
+
+    let response = request(
+        server.addr,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: from-proxy-1\r\n\r\n",
+    );
+    assert_eq!("from-proxy-1", response.body);
+}
+
+#[test]
+fn basic_auth_guards_prefixes() {
+    let router = Router::new()
+        .wrap(
+            BasicAuth::new("admin area")
+                .with_user("ferris", "crab")
+                .protect("/admin"),
+        )
+        .get("/", |_| Response::text(200, "public\n"))
+        .get("/admin/stats", |_| Response::text(200, "secret\n"));
+    let server = TestServer::start(Config::default(), router);
+
+    assert_eq!(200, server.get("/").status);
+
+    let denied = server.get("/admin/stats");
+    assert_eq!(401, denied.status);
+    assert_eq!(
+        Some("Basic realm=\"admin area\", charset=\"UTF-8\""),
+        denied.header("WWW-Authenticate")
+    );
+
+    let with_login = |credentials: &str| {
+        request(
+            server.addr,
+            &format!(
+                "GET /admin/stats HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {credentials}\r\n\r\n"
+            ),
+        )
+    };
+    // ferris:crab and ferris:wrong
+    assert_eq!("secret\n", with_login("ZmVycmlzOmNyYWI=").body);
+    assert_eq!(401, with_login("ZmVycmlzOndyb25n").status);
+}
+
+#[test]
+fn basic_auth_guards_static_files_however_the_path_is_written() {
+    let root = temp_dir("auth_root");
+    fs::create_dir_all(root.join("admin")).unwrap();
+    fs::write(root.join("admin/secret.txt"), "secret\n").unwrap();
+    fs::write(root.join("public.txt"), "public\n").unwrap();
+
+    let config = Config {
+        hosts: vec![HostConfig {
+            root: Some(root.to_path_buf()),
+            default: true,
+            ..HostConfig::default()
+        }],
+        ..Config::default()
+    };
+    let router = Router::new().wrap(
+        BasicAuth::new("admin area")
+            .with_user("ferris", "crab")
+            .protect("/admin"),
+    );
+    let server = TestServer::start(config, router);
+
+    assert_eq!("public\n", server.get("/public.txt").body);
+    for path in [
+        "/admin/secret.txt",
+        "//admin/secret.txt",
+        "/./admin/secret.txt",
+        "/%61dmin/secret.txt",
+        "/admin//secret.txt",
+    ] {
+        assert_eq!(401, server.get(path).status, "{path}");
+    }
+
+    server.stop();
+}
@@ -0,0 +1,186 @@
+mod common;
+
+use common::{temp_dir, TempDir, TestServer};
+use multithreaded_server::http::Response;
+use multithreaded_server::server::{Config, Router, Server};
+use multithreaded_server::vhost::HostConfig;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// a directory with two document roots, `old` and `new`, each with an index.html
+// naming it
+fn sites(name: &str) -> TempDir {
+    let dir = temp_dir(&format!("reload_{name}"));
+    for site in ["old", "new"] {
+        fs::create_dir_all(dir.join(site)).unwrap();
+        fs::write(dir.join(site).join("index.html"), site).unwrap();
+    }
+    dir
+}
+
+fn config(root: PathBuf, workers: usize) -> Config {
+    Config {
+        workers,
+        hosts: vec![HostConfig {
+            root: Some(root),
+            ..HostConfig::default()
+        }],
+        ..Config::default()
+    }
+}
+
+// each router answers /generation with its place in the order routers were built
+fn start(config: Config) -> Server {
+    let built = Arc::new(AtomicUsize::new(0));
+    Server::bind_hosts("127.0.0.1:0", config, move |_| {
+        let generation = built.fetch_add(1, Ordering::SeqCst) + 1;
+        Router::new().get("/generation", move |request| {
+            if request.query_param("slow").is_some() {
+                thread::sleep(Duration::from_millis(500));
+            }
+            Response::text(200, generation.to_string())
+        })
+    })
+    .unwrap()
+}
+
+fn wait_for(server: &TestServer, path: &str, expected: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let body = server.get(path).body;
+        if body == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "still serving {body:?}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn new_requests_get_the_new_config() {
+    let dir = sites("swap");
+    let server = start(config(dir.join("old"), 2));
+    let reload = server.reload_handle();
+    let server = TestServer::run(server);
+
+    assert_eq!("old", server.get("/").body);
+    assert_eq!("1", server.get("/generation").body);
+
+    // this one is already being answered when the config changes
+    let addr = server.addr;
+    let in_flight = thread::spawn(move || {
+        common::request(
+            addr,
+            "GET /generation?slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    reload.reload(config(dir.join("new"), 2)).unwrap();
+
+    assert_eq!("new", server.get("/").body);
+    assert_eq!("2", server.get("/generation").body);
+    assert_eq!("1", in_flight.join().unwrap().body);
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let dir = sites("invalid");
+    let server = start(config(dir.join("old"), 2));
+    let reload = server.reload_handle();
+    let server = TestServer::run(server);
+
+    assert!(reload.reload(config(dir.join("missing"), 2)).is_err());
+    assert!(reload.reload(config(dir.join("new"), 0)).is_err());
+
+    assert_eq!("old", server.get("/").body);
+    assert_eq!("1", server.get("/generation").body);
+}
+
+#[test]
+fn reloading_resizes_the_pool() {
+    let dir = sites("resize");
+    let server = start(config(dir.join("old"), 2));
+    let reload = server.reload_handle();
+    let server = TestServer::run(server);
+
+    reload.reload(config(dir.join("old"), 6)).unwrap();
+
+    // six slow requests at once only take about as long as one with six workers
+    let started = Instant::now();
+    let requests: Vec<_> = (0..6)
+        .map(|_| {
+            let addr = server.addr;
+            thread::spawn(move || {
+                common::request(
+                    addr,
+                    "GET /generation?slow HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                )
+            })
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(200, request.join().unwrap().status);
+    }
+    assert!(started.elapsed() < Duration::from_millis(1400));
+}
+
+#[test]
+fn watches_the_config_file() {
+    let dir = sites("watch");
+    let file = dir.join("server.json");
+    fs::write(&file, r#"{"hosts": [{"root": "old"}]}"#).unwrap();
+
+    let config = multithreaded_server::reload::load(&file).unwrap();
+    let server = TestServer::run(start(config).with_config_file(&file, true));
+    assert_eq!("old", server.get("/").body);
+
+    // a broken file is reported and ignored
+    fs::write(&file, r#"{"hosts": [{"root": "#).unwrap();
+    touch(&file, 10);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!("old", server.get("/").body);
+
+    fs::write(&file, r#"{"hosts": [{"root": "new"}]}"#).unwrap();
+    touch(&file, 20);
+    wait_for(&server, "/", "new");
+}
+
+#[cfg(unix)]
+#[test]
+fn rereads_the_config_file_on_sighup() {
+    let dir = sites("sighup");
+    let file = dir.join("server.json");
+    fs::write(&file, r#"{"hosts": [{"root": "old"}]}"#).unwrap();
+
+    let config = multithreaded_server::reload::load(&file).unwrap();
+    let server = TestServer::run(start(config).with_config_file(&file, false));
+
+    // without watching, only the signal makes the change count
+    fs::write(&file, r#"{"hosts": [{"root": "new"}]}"#).unwrap();
+    touch(&file, 10);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!("old", server.get("/").body);
+
+    let killed = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    wait_for(&server, "/", "new");
+}
+
+// moves the file's modification time ahead, as two writes within the timestamp
+// resolution of the file system would otherwise look like no change
+fn touch(path: &Path, secs: u64) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(secs))
+        .unwrap();
+}
@@ -0,0 +1,210 @@
+mod common;
+
+use common::{read_response, request, TestServer};
+use multithreaded_server::body::BodyConfig;
+use multithreaded_server::routes;
+use multithreaded_server::server::Config;
+use multithreaded_server::sse::Broadcaster;
+use std::io::{prelude::*, BufReader};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SLEEP: Duration = Duration::from_millis(500);
+const SLEEP_REQUEST: &str = "GET /sleep HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+fn start() -> TestServer {
+    TestServer::start(
+        Config::default(),
+        routes::router(SLEEP, Arc::default(), Broadcaster::new(100)),
+    )
+}
+
+#[test]
+fn serves_hello_page() {
+    let server = start();
+
+    let response = server.get("/?name=Ferris");
+
+    assert_eq!(200, response.status);
+    assert!(response.body.contains("Hello, Ferris!"));
+    assert_eq!(
+        Some("text/html; charset=utf-8"),
+        response.header("Content-Type")
+    );
+}
+
+#[test]
+fn unknown_path_is_404() {
+    let server = start();
+
+    let response = server.get("/nope");
+
+    assert_eq!(404, response.status);
+    assert!(response.body.contains("Oops!"));
+}
+
+#[test]
+fn wrong_method_is_405() {
+    let server = start();
+
+    let response = server.post("/", "text/plain", "hi");
+
+    assert_eq!(405, response.status);
+    assert_eq!(Some("GET"), response.header("Allow"));
+}
+
+#[test]
+fn echoes_form_body() {
+    let server = start();
+
+    let response = server.post(
+        "/echo",
+        "application/x-www-form-urlencoded",
+        "crab=Ferris&lang=rust",
+    );
+
+    assert_eq!(200, response.status);
+    assert_eq!(
+        r#"{"kind":"form","fields":{"crab":"Ferris","lang":"rust"}}"#,
+        response.body
+    );
+}
+
+#[test]
+fn sleep_requests_run_concurrently() {
+    let server = start();
+    let addr = server.addr;
+    let started = Instant::now();
+
+    let sleepers: Vec<_> = (0..3)
+        .map(|_| thread::spawn(move || request(addr, SLEEP_REQUEST).status))
+        .collect();
+
+    // a fast request isn't stuck behind the slow ones
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(200, server.get("/").status);
+    assert!(started.elapsed() < SLEEP);
+
+    for sleeper in sleepers {
+        assert_eq!(200, sleeper.join().unwrap());
+    }
+    // run one after another, three sleeps would take 3 * SLEEP
+    assert!(started.elapsed() < SLEEP * 2);
+}
+
+#[test]
+fn shutdown_waits_for_in_flight_requests() {
+    let server = start();
+    let addr = server.addr;
+
+    let sleeper = thread::spawn(move || request(addr, SLEEP_REQUEST).status);
+    thread::sleep(Duration::from_millis(100));
+
+    let stopping = Instant::now();
+    server.stop();
+
+    // stop() only returned once the worker had finished the sleeping request
+    assert!(stopping.elapsed() >= SLEEP - Duration::from_millis(150));
+    assert_eq!(200, sleeper.join().unwrap());
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn malformed_requests_get_4xx() {
+    let server = start();
+
+    let cases = [
+        ("garbage\r\n\r\n", 400),
+        ("GET / HTTP/1.1\r\nno colon here\r\n\r\n", 400),
+        ("GET / HTTP/2.0\r\n\r\n", 505),
+        (
+            "POST /echo HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n",
+            411,
+        ),
+        ("POST /echo HTTP/1.1\r\nContent-Length: abc\r\n\r\n", 400),
+        (
+            "POST /echo HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+            400,
+        ),
+    ];
+
+    for (raw, status) in cases {
+        assert_eq!(status, request(server.addr, raw).status, "{raw:?}");
+    }
+
+    // without a length, a GET has no body rather than a missing one
+    let get = "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\n\r\n";
+    assert_eq!(200, request(server.addr, get).status);
+
+    let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    assert_eq!(431, request(server.addr, &long_line).status);
+}
+
+#[test]
+fn oversized_body_is_413() {
+    let config = Config {
+        body: BodyConfig {
+            max_size: 8,
+            ..BodyConfig::default()
+        },
+        ..Config::default()
+    };
+    let server = TestServer::start(
+        config,
+        routes::router(SLEEP, Arc::default(), Broadcaster::new(100)),
+    );
+
+    let response = server.post("/echo", "application/json", r#"{"too": "long"}"#);
+
+    assert_eq!(413, response.status);
+}
+
+#[test]
+fn survives_clients_that_hang_up() {
+    let server = start();
+
+    for _ in 0..8 {
+        // connect, send half a request line and leave
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        stream.write_all(b"GET /sl").unwrap();
+    }
+
+    assert_eq!(200, server.get("/").status);
+}
+
+#[test]
+fn keeps_connections_alive() {
+    let config = Config {
+        keep_alive: Some(Duration::from_secs(5)),
+        ..Config::default()
+    };
+    let server = TestServer::start(
+        config,
+        routes::router(SLEEP, Arc::default(), Broadcaster::new(100)),
+    );
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    for name in ["Ferris", "Corro"] {
+        write!(
+            stream,
+            "GET /?name={name} HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        )
+        .unwrap();
+        let response = read_response(&mut reader);
+        assert_eq!(Some("keep-alive"), response.header("Connection"));
+        assert!(response.body.contains(name));
+    }
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(
+        Some("close"),
+        read_response(&mut reader).header("Connection")
+    );
+    // the server hung up
+    assert_eq!(0, reader.read(&mut [0; 1]).unwrap());
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{request, temp_dir, TestServer};
+use multithreaded_server::http::Response;
+use multithreaded_server::routes;
+use multithreaded_server::server::{Config, Router, Server};
+use multithreaded_server::session::{FileStore, MemoryStore, Sessions};
+use multithreaded_server::sse::Broadcaster;
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn get_with_cookie(addr: SocketAddr, path: &str, cookie: &str) -> common::TestResponse {
+    request(
+        addr,
+        &format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nCookie: {cookie}\r\n\r\n"),
+    )
+}
+
+// "session=abc; Path=/; ..." -> "session=abc"
+fn cookie_of(response: &common::TestResponse) -> String {
+    let set_cookie = response.header("Set-Cookie").expect("no Set-Cookie");
+    set_cookie.split(';').next().unwrap().to_string()
+}
+
+#[test]
+fn session_persists_across_requests() {
+    let server = TestServer::start(
+        Config::default(),
+        routes::router(Duration::ZERO, Arc::default(), Broadcaster::new(100)),
+    );
+
+    let first = server.get("/visits");
+    assert_eq!("Visits: 1\n", first.body);
+    let set_cookie = first.header("Set-Cookie").unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(set_cookie.contains("SameSite=Lax"));
+
+    let cookie = cookie_of(&first);
+    assert_eq!(
+        "Visits: 2\n",
+        get_with_cookie(server.addr, "/visits", &cookie).body
+    );
+
+    // a made-up id just starts a fresh session
+    let forged = "session=00000000000000000000000000000000";
+    assert_eq!(
+        "Visits: 1\n",
+        get_with_cookie(server.addr, "/visits", forged).body
+    );
+    // pages that don't touch the session don't create one
+    assert_eq!(None, server.get("/").header("Set-Cookie"));
+}
+
+fn login_router(sessions: Sessions) -> Router {
+    Router::new()
+        .wrap(sessions)
+        .get("/login", |request| {
+            let session = request.session.clone().unwrap();
+            session.renew();
+            session.insert("user", "ferris");
+            Response::text(200, "logged in\n")
+        })
+        .get("/whoami", |request| {
+            let user = request.session.as_ref().and_then(|s| s.get("user"));
+            let user = user.as_ref().and_then(|u| u.as_str()).unwrap_or("nobody");
+            Response::text(200, format!("{user}\n"))
+        })
+        .get("/logout", |request| {
+            request.session.as_ref().unwrap().destroy();
+            Response::text(200, "bye\n")
+        })
+}
+
+#[test]
+fn file_sessions_survive_restarts() {
+    let dir = temp_dir("sessions");
+    let start = || {
+        let sessions = Sessions::new(FileStore::new(dir.to_path_buf()).unwrap());
+        TestServer::start(Config::default(), login_router(sessions))
+    };
+
+    let server = start();
+    let cookie = cookie_of(&server.get("/login"));
+    server.stop();
+
+    let server = start();
+    assert_eq!(
+        "ferris\n",
+        get_with_cookie(server.addr, "/whoami", &cookie).body
+    );
+
+    let logout = get_with_cookie(server.addr, "/logout", &cookie);
+    assert!(logout.header("Set-Cookie").unwrap().contains("Max-Age=0"));
+    assert_eq!(
+        "nobody\n",
+        get_with_cookie(server.addr, "/whoami", &cookie).body
+    );
+    assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+}
+
+#[test]
+fn shared_store_keeps_sessions_across_reloads() {
+    let store = Arc::new(MemoryStore::new());
+    let shared = Arc::clone(&store);
+    let server = Server::bind_hosts("127.0.0.1:0", Config::default(), move |_| {
+        routes::router(Duration::ZERO, Arc::clone(&shared), Broadcaster::new(100))
+    })
+    .unwrap();
+    let reload = server.reload_handle();
+    let server = TestServer::run(server);
+
+    let cookie = cookie_of(&server.get("/visits"));
+    // the routers are all built anew, but on the same store
+    reload.reload(Config::default()).unwrap();
+    assert_eq!(
+        "Visits: 2\n",
+        get_with_cookie(server.addr, "/visits", &cookie).body
+    );
+    assert_eq!(1, store.len());
+}
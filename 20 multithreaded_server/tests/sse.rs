@@ -0,0 +1,107 @@
+mod common;
+
+use common::TestServer;
+use multithreaded_server::server::{Config, Router, Server};
+use multithreaded_server::sse::{Broadcaster, Event};
+use std::io::{prelude::*, BufReader};
+use std::net::TcpStream;
+use std::time::Duration;
+
+fn start(broadcaster: &Broadcaster) -> TestServer {
+    let events = broadcaster.clone();
+    let router = Router::new().get("/events", move |request| events.subscribe(request));
+
+    TestServer::start(Config::default(), router)
+}
+
+fn subscribe(server: &TestServer, extra_headers: &str) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET /events HTTP/1.1\r\nHost: localhost\r\n{extra_headers}\r\n"
+    )
+    .unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let head = read_block(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200 OK"));
+    assert!(head.contains("Content-Type: text/event-stream"));
+    reader
+}
+
+// reads up to and including the next blank line
+fn read_block(reader: &mut BufReader<TcpStream>) -> String {
+    let mut block = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line.trim_end().is_empty() {
+            return block;
+        }
+        block.push_str(&line);
+    }
+}
+
+#[test]
+fn streams_published_events() {
+    let broadcaster = Broadcaster::new(10);
+    let server = start(&broadcaster);
+    let mut reader = subscribe(&server, "");
+
+    broadcaster.publish(Event::new("hello\nworld").event("greeting"));
+
+    assert_eq!(
+        "event: greeting\nid: 1\ndata: hello\ndata: world\n",
+        read_block(&mut reader)
+    );
+    broadcaster.close();
+}
+
+#[test]
+fn resumes_from_last_event_id() {
+    let broadcaster = Broadcaster::new(2);
+    let server = start(&broadcaster);
+    for data in ["one", "two", "three", "four"] {
+        broadcaster.publish(Event::new(data));
+    }
+
+    // event 2 has already been evicted from the two-event buffer
+    let mut reader = subscribe(&server, "Last-Event-ID: 1\r\n");
+
+    assert_eq!("id: 3\ndata: three\n", read_block(&mut reader));
+    assert_eq!("id: 4\ndata: four\n", read_block(&mut reader));
+    broadcaster.close();
+}
+
+#[test]
+fn idle_streams_get_heartbeats() {
+    let broadcaster = Broadcaster::new(10)
+        .with_heartbeat(Duration::from_millis(50))
+        .with_retry(Duration::from_secs(2));
+    let server = start(&broadcaster);
+    let mut reader = subscribe(&server, "");
+
+    assert_eq!("retry: 2000\n", read_block(&mut reader));
+    assert_eq!(": heartbeat\n", read_block(&mut reader));
+    broadcaster.close();
+}
+
+#[test]
+fn shutdown_hook_ends_open_streams() {
+    let broadcaster = Broadcaster::new(10).with_heartbeat(Duration::from_millis(50));
+    let events = broadcaster.clone();
+    let router = Router::new().get("/events", move |request| events.subscribe(request));
+    let server = Server::bind("127.0.0.1:0", Config::default(), router)
+        .unwrap()
+        .with_shutdown_hook(move || broadcaster.close());
+    let server = TestServer::run(server);
+    let mut reader = subscribe(&server, "");
+
+    // returns only once the stream, and with it the worker, has ended
+    server.stop();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+}
@@ -0,0 +1,155 @@
+use multithreaded_server::hello::ThreadPool;
+use multithreaded_server::task;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn drop_finishes_queued_jobs_first() {
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let pool = ThreadPool::new(2);
+
+    for id in 0..8 {
+        let done = Arc::clone(&done);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(10));
+            done.lock().unwrap().push(id);
+        });
+    }
+
+    // the sender is dropped before the workers are joined, so every queued job still runs
+    drop(pool);
+
+    let mut done = done.lock().unwrap().clone();
+    done.sort();
+    assert_eq!((0..8).collect::<Vec<_>>(), done);
+}
+
+#[test]
+fn jobs_run_on_separate_workers() {
+    let pool = ThreadPool::new(4);
+    let barrier = Arc::new(Barrier::new(4));
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    // this only completes if all four jobs are running at the same time
+    for _ in 0..4 {
+        let barrier = Arc::clone(&barrier);
+        let sender = sender.clone();
+        pool.execute(move || {
+            barrier.wait();
+            sender.send(()).unwrap();
+        });
+    }
+
+    for _ in 0..4 {
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
+
+#[test]
+#[should_panic]
+fn zero_workers_panics() {
+    ThreadPool::new(0);
+}
+
+#[test]
+fn resize_adds_and_removes_workers() {
+    let pool = ThreadPool::new(2);
+    let monitor = pool.monitor();
+
+    pool.resize(5);
+    assert_eq!(5, pool.size());
+    assert_eq!(5, monitor.workers().len());
+
+    pool.resize(1);
+    assert_eq!(1, pool.size());
+    // the surplus workers stop once they take their turn at the queue
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while monitor.workers().len() > 1 {
+        assert!(std::time::Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    pool.execute(move || sender.send(()).unwrap());
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+}
+
+#[test]
+fn waiting_futures_free_their_worker() {
+    let pool = ThreadPool::new(1);
+    let started = std::time::Instant::now();
+
+    // all of them sleep at once on the one worker
+    let handles: Vec<_> = (0..10u64)
+        .map(|i| {
+            pool.spawn_future(async move {
+                task::sleep(Duration::from_millis(100)).await;
+                i * 2
+            })
+        })
+        .collect();
+
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!((0..10).map(|i| i * 2).collect::<Vec<_>>(), results);
+    assert!(started.elapsed() < Duration::from_millis(900));
+}
+
+#[test]
+fn futures_talk_over_channels() {
+    let pool = ThreadPool::new(2);
+    let (sender, mut receiver) = task::channel();
+
+    let consumer = pool.spawn_future(async move {
+        let mut sum = 0;
+        while let Some(n) = receiver.recv().await {
+            sum += n;
+        }
+        sum
+    });
+    let producer = pool.spawn_future(async move {
+        for n in 1..=10 {
+            sender.send(n).unwrap();
+            task::sleep(Duration::from_millis(1)).await;
+        }
+    });
+
+    // a handle is a future too, so it can be awaited from another task
+    let total = task::block_on(async {
+        producer.await.unwrap();
+        consumer.await
+    });
+    assert_eq!(Some(55), total);
+}
+
+#[test]
+fn panicking_job_leaves_its_worker_running() {
+    let pool = ThreadPool::new(1);
+    let monitor = pool.monitor();
+
+    pool.execute(|| panic!("oops"));
+    let (sender, receiver) = std::sync::mpsc::channel();
+    pool.execute(move || sender.send(()).unwrap());
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // the worker that ran both jobs goes back to being idle
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while monitor.workers()[0].busy_since.is_some() {
+        assert!(std::time::Instant::now() < deadline);
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(1, monitor.workers().len());
+}
+
+#[test]
+fn panicking_future_leaves_the_pool_working() {
+    let pool = ThreadPool::new(1);
+
+    let failed = pool.spawn_future(async {
+        task::sleep(Duration::from_millis(1)).await;
+        panic!("oops");
+    });
+    assert_eq!(None::<()>, failed.join());
+
+    assert_eq!(Some(4), pool.spawn_future(async { 2 + 2 }).join());
+}
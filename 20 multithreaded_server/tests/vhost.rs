@@ -0,0 +1,214 @@
+mod common;
+
+use common::{request, temp_dir, TempDir, TestResponse, TestServer};
+use multithreaded_server::http::Response;
+use multithreaded_server::server::{Config, Router, Server};
+use multithreaded_server::vhost::HostConfig;
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::path::PathBuf;
+
+struct Sites {
+    // dropped in this order, so the server is gone before its files are
+    server: TestServer,
+    dir: TempDir,
+}
+
+// a.test has a route, an error page and a log; b.test is the default host
+fn start(name: &str) -> Sites {
+    let dir = temp_dir(&format!("vhost_{name}"));
+    fs::create_dir_all(dir.join("a/docs")).unwrap();
+    fs::create_dir_all(dir.join("b")).unwrap();
+    fs::write(dir.join("a/index.html"), "site a").unwrap();
+    fs::write(dir.join("a/docs/index.html"), "docs").unwrap();
+    fs::write(dir.join("a/404.html"), "a has no such page").unwrap();
+    fs::write(dir.join("b/index.html"), "site b").unwrap();
+    fs::write(dir.join("a/.env"), "SECRET=1").unwrap();
+    fs::create_dir_all(dir.join("a/.git")).unwrap();
+    fs::write(dir.join("a/.git/config"), "[core]").unwrap();
+    fs::create_dir_all(dir.join("b/.well-known")).unwrap();
+    fs::write(dir.join("b/.well-known/shown"), "shown").unwrap();
+    fs::create_dir_all(dir.join("a/pub/sub dir")).unwrap();
+    fs::write(dir.join("a/pub/big.txt"), "0123456789").unwrap();
+    fs::write(dir.join("a/pub/a <b>.txt"), "x").unwrap();
+    fs::write(dir.join("a/pub/.hidden"), "").unwrap();
+    fs::create_dir_all(dir.join("a/private")).unwrap();
+    fs::create_dir_all(dir.join("a/example.com")).unwrap();
+
+    let config = Config {
+        hosts: vec![
+            HostConfig {
+                names: vec!["a.test".to_string(), "www.a.test".to_string()],
+                root: Some(dir.join("a")),
+                error_pages: HashMap::from([(404, PathBuf::from("404.html"))]),
+                listings: vec!["/pub".to_string()],
+                log: Some(dir.join("a.log")),
+                dotfiles: false,
+                default: false,
+            },
+            HostConfig {
+                names: vec!["b.test".to_string()],
+                root: Some(dir.join("b")),
+                dotfiles: true,
+                default: true,
+                ..HostConfig::default()
+            },
+        ],
+        ..Config::default()
+    };
+
+    let server = Server::bind_hosts("127.0.0.1:0", config, |host| {
+        if host.names[0] == "a.test" {
+            Router::new().get("/hello", |_| Response::text(200, "hello from a"))
+        } else {
+            Router::new()
+        }
+    })
+    .unwrap();
+
+    Sites {
+        server: TestServer::run(server),
+        dir,
+    }
+}
+
+impl Sites {
+    fn get(&self, host: &str, path: &str) -> TestResponse {
+        request(
+            self.server.addr,
+            &format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+        )
+    }
+}
+
+#[test]
+fn host_header_selects_the_site() {
+    let sites = start("select");
+
+    assert_eq!("site a", sites.get("a.test", "/").body);
+    assert_eq!("site a", sites.get("a.test:7878", "/").body);
+    assert_eq!("hello from a", sites.get("WWW.A.TEST", "/hello").body);
+    assert_eq!("site b", sites.get("b.test", "/").body);
+    assert_eq!(404, sites.get("b.test", "/hello").status);
+}
+
+#[test]
+fn unknown_hosts_go_to_the_default() {
+    let sites = start("default");
+
+    assert_eq!("site b", sites.get("elsewhere.test", "/").body);
+
+    let http10 = request(sites.server.addr, "GET / HTTP/1.0\r\n\r\n");
+    assert_eq!("site b", http10.body);
+}
+
+#[test]
+fn http11_without_host_is_400() {
+    let sites = start("missing");
+
+    assert_eq!(
+        400,
+        request(sites.server.addr, "GET / HTTP/1.1\r\n\r\n").status
+    );
+
+    let twice = "GET / HTTP/1.1\r\nHost: a.test\r\nHost: b.test\r\n\r\n";
+    assert_eq!(400, request(sites.server.addr, twice).status);
+}
+
+#[test]
+fn serves_error_pages_and_directories() {
+    let sites = start("pages");
+
+    let missing = sites.get("a.test", "/nope");
+    assert_eq!(404, missing.status);
+    assert_eq!("a has no such page", missing.body);
+
+    let docs = sites.get("a.test", "/docs");
+    assert_eq!(301, docs.status);
+    assert_eq!(Some("/docs/"), docs.header("Location"));
+    assert_eq!("docs", sites.get("a.test", "/docs/").body);
+
+    // redirects go to the normalized path, keeping the query, and stay on the site
+    let location = |path| {
+        sites
+            .get("a.test", path)
+            .header("Location")
+            .map(str::to_string)
+    };
+    assert_eq!(
+        Some("/docs/?page=2".to_string()),
+        location("/./docs?page=2")
+    );
+    assert_eq!(Some("/example.com/".to_string()), location("//example.com"));
+
+    assert_eq!(404, sites.get("a.test", "/../b/index.html").status);
+
+    // hidden files stay hidden, however the path is written
+    assert_eq!(404, sites.get("a.test", "/.env").status);
+    assert_eq!(404, sites.get("a.test", "/.git/config").status);
+    assert_eq!(404, sites.get("a.test", "/%2egit/config").status);
+    assert_eq!("shown", sites.get("b.test", "/.well-known/shown").body);
+}
+
+#[test]
+fn answers_head_with_headers_alone() {
+    let sites = start("head");
+
+    let mut stream = TcpStream::connect(sites.server.addr).unwrap();
+    stream
+        .write_all(b"HEAD / HTTP/1.1\r\nHost: a.test\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Content-Length: 6\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\n"), "{response}");
+}
+
+#[test]
+fn lists_directories_where_enabled() {
+    let sites = start("listings");
+
+    let listing = sites.get("a.test", "/pub/");
+    assert_eq!(200, listing.status);
+    assert!(listing.body.contains("<h1>Index of /pub/</h1>"));
+    assert!(listing
+        .body
+        .contains("<a href=\"a%20%3Cb%3E.txt\">a &lt;b&gt;.txt</a>"));
+    assert!(listing.body.contains("<a href=\"sub%20dir/\">sub dir/</a>"));
+    assert!(!listing.body.contains(".hidden"));
+
+    // directories first, then the biggest file
+    let json = request(
+        sites.server.addr,
+        "GET /pub/?sort=size&order=desc HTTP/1.1\r\nHost: a.test\r\nAccept: application/json\r\n\r\n",
+    );
+    assert_eq!(Some("application/json"), json.header("Content-Type"));
+    let names: Vec<_> = json
+        .body
+        .match_indices("\"name\":")
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(3, names.len());
+    assert!(json.body[names[0]..].starts_with("\"name\":\"sub dir\""));
+    assert!(json.body[names[1]..].starts_with("\"name\":\"big.txt\""));
+
+    // disabled outside the configured paths
+    assert_eq!(404, sites.get("a.test", "/private/").status);
+}
+
+#[test]
+fn each_host_logs_its_own_requests() {
+    let sites = start("logs");
+
+    sites.get("a.test", "/hello?x=1");
+    sites.get("b.test", "/");
+
+    let log = fs::read_to_string(sites.dir.join("a.log")).unwrap();
+    assert_eq!(1, log.lines().count());
+    assert!(log.starts_with("127.0.0.1 - - ["));
+    assert!(log.contains("\"GET /hello?x=1 HTTP/1.1\" 200 12 \"-\" \"-\""));
+}